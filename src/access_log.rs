@@ -0,0 +1,103 @@
+use std::{
+    cell::Cell,
+    time::{Duration, Instant},
+};
+
+use axum::{
+    extract::State,
+    http::{HeaderValue, Request},
+    middleware::Next,
+    response::Response,
+};
+use rand::Rng;
+
+use crate::{cache::CACHE_STATUS_HEADER, privacy::Privacy};
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Longest client-supplied request id we pass along; anything longer is replaced.
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+tokio::task_local! {
+    static REQUEST: RequestContext;
+}
+
+struct RequestContext {
+    id: HeaderValue,
+    /// Time spent waiting on StationAPI, summed over every attempt.
+    upstream_latency: Cell<Option<Duration>>,
+}
+
+/// Id of the request being served, for forwarding to StationAPI.
+pub fn request_id() -> Option<HeaderValue> {
+    REQUEST.try_with(|r| r.id.clone()).ok()
+}
+
+/// Adds a StationAPI call attempt to the current request's upstream latency.
+pub fn record_upstream_latency(elapsed: Duration) {
+    let _ = REQUEST.try_with(|r| {
+        let total = r.upstream_latency.get().unwrap_or_default() + elapsed;
+        r.upstream_latency.set(Some(total));
+    });
+}
+
+/// Writes one `access` log line per request and tags request and response with
+/// an `X-Request-Id`, reusing the client's one when it sent a usable id.
+///
+/// Coordinates in the query are coarsened according to `privacy` before they are logged.
+pub async fn log<B>(
+    State(privacy): State<Privacy>,
+    mut request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let id = request
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .filter(|v| !v.is_empty() && v.len() <= MAX_REQUEST_ID_LEN && v.to_str().is_ok())
+        .cloned()
+        .unwrap_or_else(generate_request_id);
+    request.headers_mut().insert(REQUEST_ID_HEADER, id.clone());
+
+    let method = request.method().clone();
+    let path = request.uri().path().to_string();
+    let query = privacy.redact_query(request.uri().query().unwrap_or_default());
+
+    let context = RequestContext {
+        id: id.clone(),
+        upstream_latency: Cell::new(None),
+    };
+    let start = Instant::now();
+    let (upstream_latency, mut response) = REQUEST
+        .scope(context, async {
+            let response = next.run(request).await;
+            let upstream_latency = REQUEST.with(|r| r.upstream_latency.get());
+            (upstream_latency, response)
+        })
+        .await;
+    let latency = start.elapsed();
+
+    let cache_status = response
+        .headers()
+        .get(CACHE_STATUS_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string);
+    tracing::info!(
+        target: "access",
+        request_id = id.to_str().unwrap_or_default(),
+        method = %method,
+        path = %path,
+        query = %query,
+        status = response.status().as_u16(),
+        latency_ms = latency.as_secs_f64() * 1000.0,
+        upstream_latency_ms = upstream_latency.map(|l| l.as_secs_f64() * 1000.0),
+        cache_status = cache_status.as_deref(),
+    );
+
+    response.headers_mut().insert(REQUEST_ID_HEADER, id);
+    response
+}
+
+fn generate_request_id() -> HeaderValue {
+    let id: u128 = rand::thread_rng().gen();
+    HeaderValue::from_str(&format!("{:032x}", id)).expect("Hex digits are a valid header value.")
+}
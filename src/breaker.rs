@@ -0,0 +1,441 @@
+use std::{
+    error::Error as _,
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+use tonic::{Code, Status};
+use tower::{Layer, Service};
+
+pub type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// gRPC codes that mean StationAPI itself is in trouble.
+const FAILING_CODES: [Code; 3] = [Code::DeadlineExceeded, Code::Internal, Code::Unavailable];
+
+/// Returned instead of calling StationAPI while the circuit is open.
+#[derive(Debug, Clone, Copy)]
+pub struct CircuitOpen {
+    pub retry_after: Duration,
+}
+
+impl fmt::Display for CircuitOpen {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "The circuit to StationAPI is open; retry in {:?}.",
+            self.retry_after
+        )
+    }
+}
+
+impl std::error::Error for CircuitOpen {}
+
+#[derive(Debug, Clone, Copy)]
+enum State {
+    Closed {
+        failures: u32,
+    },
+    Open {
+        until: Instant,
+    },
+    /// A single probe request is in flight to see whether StationAPI recovered.
+    HalfOpen,
+}
+
+/// Shared state of the circuit breaker in front of StationAPI.
+///
+/// Opens after `threshold` consecutive failures, rejects calls for `open_for`,
+/// then lets one probe through and closes again if it succeeds.
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    threshold: u32,
+    open_for: Duration,
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BreakerStatus {
+    pub state: &'static str,
+    pub consecutive_failures: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retry_after_secs: Option<u64>,
+}
+
+impl CircuitBreaker {
+    /// A `threshold` of zero disables the breaker.
+    pub fn new(threshold: u32, open_for: Duration) -> Self {
+        Self {
+            threshold,
+            open_for,
+            state: Mutex::new(State::Closed { failures: 0 }),
+        }
+    }
+
+    /// Decides whether a call may go through right now; `Ok(true)` means it is the
+    /// half-open probe.
+    fn acquire(&self) -> Result<bool, CircuitOpen> {
+        if self.threshold == 0 {
+            return Ok(false);
+        }
+
+        let mut state = self.state.lock().unwrap();
+        match *state {
+            State::Closed { .. } => Ok(false),
+            State::Open { until } => {
+                let now = Instant::now();
+                if now < until {
+                    return Err(CircuitOpen {
+                        retry_after: until - now,
+                    });
+                }
+                *state = State::HalfOpen;
+                Ok(true)
+            }
+            State::HalfOpen => Err(CircuitOpen {
+                retry_after: Duration::from_secs(1),
+            }),
+        }
+    }
+
+    /// Whether a call would currently be let through, without claiming a probe.
+    pub fn is_available(&self) -> bool {
+        match *self.state.lock().unwrap() {
+            State::Closed { .. } => true,
+            State::Open { until } => Instant::now() >= until,
+            State::HalfOpen => self.threshold == 0,
+        }
+    }
+
+    /// Starts tracking a call that is about to send `request`; see [`Attempt`].
+    pub fn attempt<T>(&self, request: &mut tonic::Request<T>) -> Attempt<'_> {
+        let claim = ProbeClaim::default();
+        request.extensions_mut().insert(claim.clone());
+        Attempt {
+            breaker: self,
+            claim,
+            settled: false,
+        }
+    }
+
+    /// Lets a request through the layer, marking its [`Attempt`] if it took the probe.
+    fn admit(&self, extensions: &http::Extensions) -> Result<(), CircuitOpen> {
+        if self.acquire()? {
+            if let Some(claim) = extensions.get::<ProbeClaim>() {
+                claim.0.store(true, Ordering::Relaxed);
+            }
+        }
+        Ok(())
+    }
+
+    fn record(&self, success: bool) {
+        if self.threshold == 0 {
+            return;
+        }
+
+        let mut state = self.state.lock().unwrap();
+        *state = match (*state, success) {
+            (_, true) => State::Closed { failures: 0 },
+            (State::Closed { failures }, false) if failures + 1 < self.threshold => State::Closed {
+                failures: failures + 1,
+            },
+            (State::Open { until }, false) => State::Open { until },
+            (_, false) => {
+                tracing::warn!("Opening the circuit to StationAPI for {:?}.", self.open_for);
+                State::Open {
+                    until: Instant::now() + self.open_for,
+                }
+            }
+        };
+    }
+
+    /// Gives up a half-open probe that never finished, so the next call can probe instead.
+    fn abandon(&self) {
+        let mut state = self.state.lock().unwrap();
+        if let State::HalfOpen = *state {
+            *state = State::Open {
+                until: Instant::now(),
+            };
+        }
+    }
+
+    pub fn status(&self) -> BreakerStatus {
+        let state = *self.state.lock().unwrap();
+        match state {
+            State::Closed { failures } => BreakerStatus {
+                state: "closed",
+                consecutive_failures: failures,
+                retry_after_secs: None,
+            },
+            State::Open { until } => BreakerStatus {
+                state: "open",
+                consecutive_failures: self.threshold,
+                retry_after_secs: Some(
+                    until
+                        .saturating_duration_since(Instant::now())
+                        .as_secs_f64()
+                        .ceil() as u64,
+                ),
+            },
+            State::HalfOpen => BreakerStatus {
+                state: "half_open",
+                consecutive_failures: self.threshold,
+                retry_after_secs: None,
+            },
+        }
+    }
+}
+
+/// Set on a request's extensions by the layer when that request took the half-open probe.
+#[derive(Clone, Default)]
+struct ProbeClaim(Arc<AtomicBool>);
+
+/// Outcome of one call, recorded by the caller once StationAPI's final status is
+/// known. A call dropped before that, e.g. because the client went away, does not
+/// count either way, and gives up the probe if it held it.
+pub struct Attempt<'a> {
+    breaker: &'a CircuitBreaker,
+    claim: ProbeClaim,
+    settled: bool,
+}
+
+impl Attempt<'_> {
+    pub fn succeeded(mut self) {
+        self.settled = true;
+        self.breaker.record(true);
+    }
+
+    /// Records an error status; ones the breaker raised itself are not counted.
+    pub fn failed(mut self, status: &Status) {
+        self.settled = true;
+        if !is_circuit_open(status) {
+            self.breaker.record(!is_failure(status));
+        }
+    }
+
+    /// Our own deadline fired before StationAPI answered.
+    pub fn timed_out(mut self) {
+        self.settled = true;
+        self.breaker.record(false);
+    }
+}
+
+impl Drop for Attempt<'_> {
+    fn drop(&mut self) {
+        if !self.settled && self.claim.0.load(Ordering::Relaxed) {
+            self.breaker.abandon();
+        }
+    }
+}
+
+pub fn is_circuit_open(status: &Status) -> bool {
+    status
+        .source()
+        .is_some_and(|e| e.downcast_ref::<CircuitOpen>().is_some())
+}
+
+/// Whether a status means StationAPI is failing, rather than refusing a bad request.
+fn is_failure(status: &Status) -> bool {
+    // Transport errors surface as `Unknown` with the underlying error attached.
+    FAILING_CODES.contains(&status.code())
+        || (status.code() == Code::Unknown && status.source().is_some())
+}
+
+/// Tower layer that puts a [`CircuitBreaker`] in front of the StationAPI transport.
+///
+/// It only turns calls away while the circuit is open; outcomes are recorded by
+/// the caller through [`CircuitBreaker::attempt`], from the final gRPC status.
+#[derive(Clone)]
+pub struct CircuitBreakerLayer {
+    breaker: Arc<CircuitBreaker>,
+}
+
+impl CircuitBreakerLayer {
+    pub fn new(breaker: Arc<CircuitBreaker>) -> Self {
+        Self { breaker }
+    }
+}
+
+impl<S> Layer<S> for CircuitBreakerLayer {
+    type Service = CircuitBreakerService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        CircuitBreakerService {
+            inner,
+            breaker: Arc::clone(&self.breaker),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct CircuitBreakerService<S> {
+    inner: S,
+    breaker: Arc<CircuitBreaker>,
+}
+
+impl<S, ReqBody, ResBody> Service<http::Request<ReqBody>> for CircuitBreakerService<S>
+where
+    S: Service<http::Request<ReqBody>, Response = http::Response<ResBody>>,
+    S::Error: Into<BoxError>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = BoxError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, request: http::Request<ReqBody>) -> Self::Future {
+        if let Err(open) = self.breaker.admit(request.extensions()) {
+            return Box::pin(async move { Err(open.into()) });
+        }
+
+        let future = self.inner.call(request);
+        Box::pin(async move { future.await.map_err(Into::into) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Starts a call the way `Client::call` does and passes it through the layer.
+    fn start(breaker: &CircuitBreaker) -> Result<Attempt<'_>, CircuitOpen> {
+        let mut request = tonic::Request::new(());
+        let attempt = breaker.attempt(&mut request);
+        let mut extensions = http::Extensions::new();
+        extensions.insert(attempt.claim.clone());
+        breaker.admit(&extensions).map(|()| attempt)
+    }
+
+    fn open_breaker(open_for: Duration) -> CircuitBreaker {
+        let breaker = CircuitBreaker::new(2, open_for);
+        for _ in 0..2 {
+            start(&breaker).unwrap().timed_out();
+        }
+        breaker
+    }
+
+    #[test]
+    fn opens_after_consecutive_failures() {
+        let breaker = CircuitBreaker::new(3, Duration::from_secs(60));
+        for _ in 0..2 {
+            start(&breaker)
+                .unwrap()
+                .failed(&Status::unavailable("down"));
+        }
+        assert_eq!(breaker.status().state, "closed");
+
+        start(&breaker).unwrap().timed_out();
+        assert_eq!(breaker.status().state, "open");
+        assert!(start(&breaker).is_err());
+        assert!(!breaker.is_available());
+    }
+
+    #[test]
+    fn success_resets_the_failure_count() {
+        let breaker = CircuitBreaker::new(2, Duration::from_secs(60));
+        start(&breaker).unwrap().timed_out();
+        start(&breaker).unwrap().succeeded();
+        start(&breaker).unwrap().timed_out();
+        assert_eq!(breaker.status().state, "closed");
+        assert_eq!(breaker.status().consecutive_failures, 1);
+    }
+
+    #[test]
+    fn half_open_lets_one_probe_through() {
+        let breaker = open_breaker(Duration::ZERO);
+        let _probe = start(&breaker).unwrap();
+        assert_eq!(breaker.status().state, "half_open");
+        assert!(start(&breaker).is_err());
+    }
+
+    #[test]
+    fn successful_probe_closes_the_circuit() {
+        let breaker = open_breaker(Duration::ZERO);
+        start(&breaker).unwrap().succeeded();
+        assert_eq!(breaker.status().state, "closed");
+        assert!(start(&breaker).is_ok());
+    }
+
+    #[test]
+    fn failed_probe_reopens_the_circuit() {
+        let breaker = open_breaker(Duration::ZERO);
+        start(&breaker).unwrap().failed(&Status::internal("boom"));
+        assert_eq!(breaker.status().state, "open");
+    }
+
+    #[test]
+    fn abandoned_probe_lets_the_next_call_probe() {
+        let breaker = open_breaker(Duration::ZERO);
+        drop(start(&breaker).unwrap());
+        assert_eq!(breaker.status().state, "open");
+        let _probe = start(&breaker).unwrap();
+        assert_eq!(breaker.status().state, "half_open");
+    }
+
+    #[test]
+    fn cancelled_call_does_not_give_up_another_calls_probe() {
+        let breaker = CircuitBreaker::new(1, Duration::ZERO);
+        // Let through while the circuit was still closed.
+        let earlier = start(&breaker).unwrap();
+        start(&breaker).unwrap().timed_out();
+        let _probe = start(&breaker).unwrap();
+        assert_eq!(breaker.status().state, "half_open");
+
+        drop(earlier);
+        assert_eq!(breaker.status().state, "half_open");
+        assert!(start(&breaker).is_err());
+    }
+
+    #[test]
+    fn cancelled_calls_are_not_failures() {
+        let breaker = CircuitBreaker::new(1, Duration::from_secs(60));
+        drop(start(&breaker).unwrap());
+        assert_eq!(breaker.status().state, "closed");
+        assert_eq!(breaker.status().consecutive_failures, 0);
+    }
+
+    #[test]
+    fn client_errors_are_not_failures() {
+        let breaker = CircuitBreaker::new(1, Duration::from_secs(60));
+        for status in [
+            Status::not_found("no such station"),
+            Status::invalid_argument("bad id"),
+            Status::unknown("no source attached"),
+        ] {
+            start(&breaker).unwrap().failed(&status);
+        }
+        assert_eq!(breaker.status().state, "closed");
+    }
+
+    #[test]
+    fn own_rejections_are_not_counted() {
+        let breaker = CircuitBreaker::new(1, Duration::from_secs(60));
+        let open = Status::from_error(Box::new(CircuitOpen {
+            retry_after: Duration::from_secs(1),
+        }));
+        assert!(is_circuit_open(&open));
+        start(&breaker).unwrap().failed(&open);
+        assert_eq!(breaker.status().state, "closed");
+    }
+
+    #[test]
+    fn zero_threshold_disables_the_breaker() {
+        let breaker = CircuitBreaker::new(0, Duration::from_secs(60));
+        for _ in 0..10 {
+            start(&breaker).unwrap().timed_out();
+        }
+        assert_eq!(breaker.status().state, "closed");
+        assert!(start(&breaker).is_ok());
+    }
+}
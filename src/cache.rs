@@ -0,0 +1,413 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+
+use crate::station_api::Station;
+
+/// Response header telling the client how the cache answered.
+pub const CACHE_STATUS_HEADER: &str = "x-cache-status";
+
+/// Grid cell a coordinate falls into, plus the number of stations asked for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CellKey {
+    lat: i64,
+    lon: i64,
+    limit: u32,
+}
+
+struct Entry {
+    stations: Arc<Vec<Station>>,
+    inserted_at: Instant,
+    /// Position in `Entries::order`.
+    seq: u64,
+}
+
+#[derive(Default)]
+struct Entries {
+    map: HashMap<CellKey, Entry>,
+    /// Keys oldest first. Records of keys re-inserted or dropped since are left in
+    /// place and skipped, told apart by `seq`.
+    order: VecDeque<(CellKey, u64)>,
+    next_seq: u64,
+}
+
+impl Entries {
+    fn is_current(map: &HashMap<CellKey, Entry>, key: &CellKey, seq: u64) -> bool {
+        map.get(key).is_some_and(|e| e.seq == seq)
+    }
+
+    fn evict_oldest(&mut self) {
+        while let Some((key, seq)) = self.order.pop_front() {
+            if Self::is_current(&self.map, &key, seq) {
+                self.map.remove(&key);
+                return;
+            }
+        }
+    }
+
+    /// Drops the records that would be skipped.
+    fn compact(&mut self) {
+        let map = &self.map;
+        self.order
+            .retain(|(key, seq)| Self::is_current(map, key, *seq));
+    }
+}
+
+/// How long entries stay usable, in the spirit of RFC 5861.
+#[derive(Debug, Clone, Copy)]
+pub struct CacheTtl {
+    /// Entries younger than this are served as-is.
+    pub fresh: Duration,
+    /// For this long past `fresh`, entries are served while being refreshed in the background.
+    pub stale_while_revalidate: Duration,
+    /// For this long past `fresh`, entries are served when StationAPI fails.
+    pub stale_if_error: Duration,
+}
+
+/// What the cache holds for a cell.
+pub enum Lookup {
+    Fresh(Arc<Vec<Station>>),
+    /// Within the stale-while-revalidate window.
+    Stale(Arc<Vec<Station>>),
+    /// Too old to serve unless StationAPI fails.
+    Expired(Arc<Vec<Station>>),
+    Miss,
+}
+
+/// How a response was produced, reported in [`CACHE_STATUS_HEADER`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheStatus {
+    Hit,
+    Miss,
+    Stale,
+    StaleIfError,
+}
+
+impl CacheStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            CacheStatus::Hit => "hit",
+            CacheStatus::Miss => "miss",
+            CacheStatus::Stale => "stale",
+            CacheStatus::StaleIfError => "stale-if-error",
+        }
+    }
+}
+
+/// Bounded cache of `/nearby` upstream results keyed by grid cell.
+///
+/// Coordinates are snapped to a square grid of `grid` degrees, so devices polling
+/// from the same platform share one entry.
+pub struct NearbyCache {
+    grid: f64,
+    ttl: CacheTtl,
+    capacity: usize,
+    extra_candidates: u32,
+    entries: Mutex<Entries>,
+    refreshing: Mutex<HashSet<CellKey>>,
+    hits: AtomicU64,
+    stale_hits: AtomicU64,
+    stale_if_error_hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub stale_hits: u64,
+    pub stale_if_error_hits: u64,
+    pub misses: u64,
+    pub entries: usize,
+    pub capacity: usize,
+}
+
+impl NearbyCache {
+    /// A `capacity` of zero disables the cache.
+    ///
+    /// Each cell holds `extra_candidates` more stations than asked for, so the
+    /// nearest ones to any point in the cell are among them, not just the nearest
+    /// ones to its centre.
+    pub fn new(grid: f64, ttl: CacheTtl, capacity: usize, extra_candidates: u32) -> Self {
+        assert!(grid > 0.0, "The cache grid size must be positive.");
+        Self {
+            grid,
+            ttl,
+            capacity,
+            extra_candidates,
+            entries: Mutex::new(Entries::default()),
+            refreshing: Mutex::new(HashSet::new()),
+            hits: AtomicU64::new(0),
+            stale_hits: AtomicU64::new(0),
+            stale_if_error_hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.capacity > 0
+    }
+
+    pub fn key(&self, latitude: f64, longitude: f64, limit: u32) -> CellKey {
+        CellKey {
+            lat: (latitude / self.grid).floor() as i64,
+            lon: (longitude / self.grid).floor() as i64,
+            limit,
+        }
+    }
+
+    /// How many stations to fetch for a cell, to be re-ranked per request.
+    pub fn fetch_limit(&self, key: &CellKey) -> u32 {
+        key.limit.saturating_add(self.extra_candidates)
+    }
+
+    /// Centre of the cell, which is what gets sent upstream on a miss.
+    pub fn center(&self, key: &CellKey) -> (f64, f64) {
+        (
+            (key.lat as f64 + 0.5) * self.grid,
+            (key.lon as f64 + 0.5) * self.grid,
+        )
+    }
+
+    pub fn get(&self, key: &CellKey) -> Lookup {
+        let mut entries = self.entries.lock().unwrap();
+        let lookup = match entries.map.get(key) {
+            Some(entry) => {
+                let age = entry.inserted_at.elapsed();
+                let stations = Arc::clone(&entry.stations);
+                if age < self.ttl.fresh {
+                    Lookup::Fresh(stations)
+                } else if age < self.ttl.fresh + self.ttl.stale_while_revalidate {
+                    Lookup::Stale(stations)
+                } else if age < self.ttl.fresh + self.ttl.stale_if_error {
+                    Lookup::Expired(stations)
+                } else {
+                    entries.map.remove(key);
+                    Lookup::Miss
+                }
+            }
+            None => Lookup::Miss,
+        };
+
+        // Expired entries are counted once it is known whether they were served,
+        // see `record_expired`.
+        let counter = match lookup {
+            Lookup::Fresh(_) => &self.hits,
+            Lookup::Stale(_) => &self.stale_hits,
+            Lookup::Miss => &self.misses,
+            Lookup::Expired(_) => return lookup,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        lookup
+    }
+
+    /// Counts an expired lookup as a stale-if-error hit if it was `served` because
+    /// StationAPI failed, or as a miss otherwise.
+    pub fn record_expired(&self, served: bool) {
+        let counter = if served {
+            &self.stale_if_error_hits
+        } else {
+            &self.misses
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn insert(&self, key: CellKey, stations: Vec<Station>) {
+        if !self.is_enabled() {
+            return;
+        }
+
+        let mut entries = self.entries.lock().unwrap();
+        // Every entry lives equally long, so the oldest one is also the first to expire.
+        if entries.map.len() >= self.capacity && !entries.map.contains_key(&key) {
+            entries.evict_oldest();
+        }
+
+        let seq = entries.next_seq;
+        entries.next_seq += 1;
+        entries.map.insert(
+            key,
+            Entry {
+                stations: Arc::new(stations),
+                inserted_at: Instant::now(),
+                seq,
+            },
+        );
+        entries.order.push_back((key, seq));
+
+        // Drop skipped records once they outnumber live ones, keeping `order` bounded.
+        if entries.order.len() > 2 * self.capacity {
+            entries.compact();
+        }
+    }
+
+    /// Claims the background refresh of a cell; returns `false` if one is already running.
+    pub fn begin_refresh(&self, key: CellKey) -> bool {
+        self.refreshing.lock().unwrap().insert(key)
+    }
+
+    pub fn end_refresh(&self, key: &CellKey) {
+        self.refreshing.lock().unwrap().remove(key);
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            stale_hits: self.stale_hits.load(Ordering::Relaxed),
+            stale_if_error_hits: self.stale_if_error_hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            entries: self.entries.lock().unwrap().map.len(),
+            capacity: self.capacity,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TTL: CacheTtl = CacheTtl {
+        fresh: Duration::from_secs(10),
+        stale_while_revalidate: Duration::from_secs(20),
+        stale_if_error: Duration::from_secs(60),
+    };
+
+    fn cache(capacity: usize) -> NearbyCache {
+        NearbyCache::new(0.001, TTL, capacity, 5)
+    }
+
+    fn station(id: u32) -> Station {
+        Station {
+            id,
+            ..Default::default()
+        }
+    }
+
+    /// Inserts an entry as if it had been inserted `age` ago.
+    fn insert_aged(cache: &NearbyCache, key: CellKey, age: Duration) {
+        cache.insert(key, vec![station(key.limit)]);
+        let mut entries = cache.entries.lock().unwrap();
+        entries.map.get_mut(&key).unwrap().inserted_at -= age;
+    }
+
+    fn is_cached(cache: &NearbyCache, key: &CellKey) -> bool {
+        cache.entries.lock().unwrap().map.contains_key(key)
+    }
+
+    #[test]
+    fn snaps_coordinates_to_the_grid() {
+        let cache = cache(10);
+        let key = cache.key(35.6812, 139.7671, 3);
+        assert_eq!(key, cache.key(35.6819, 139.7679, 3));
+        assert_ne!(key, cache.key(35.6821, 139.7671, 3));
+        assert_ne!(key, cache.key(35.6812, 139.7671, 4));
+
+        let (latitude, longitude) = cache.center(&key);
+        assert!((latitude - 35.6815).abs() < 1e-9);
+        assert!((longitude - 139.7675).abs() < 1e-9);
+        assert_eq!(cache.fetch_limit(&key), 8);
+        assert_eq!(cache.fetch_limit(&cache.key(0.0, 0.0, u32::MAX)), u32::MAX);
+    }
+
+    #[test]
+    fn serves_each_ttl_window() {
+        let cache = cache(10);
+        let key = |limit| cache.key(35.0, 139.0, limit);
+        insert_aged(&cache, key(1), Duration::from_secs(5));
+        insert_aged(&cache, key(2), Duration::from_secs(15));
+        insert_aged(&cache, key(3), Duration::from_secs(40));
+        insert_aged(&cache, key(4), Duration::from_secs(80));
+
+        assert!(matches!(cache.get(&key(1)), Lookup::Fresh(_)));
+        assert!(matches!(cache.get(&key(2)), Lookup::Stale(_)));
+        assert!(matches!(cache.get(&key(3)), Lookup::Expired(_)));
+        assert!(matches!(cache.get(&key(4)), Lookup::Miss));
+        assert!(!is_cached(&cache, &key(4)));
+        assert!(matches!(cache.get(&key(5)), Lookup::Miss));
+
+        let stats = cache.stats();
+        assert_eq!(stats.hits, 1);
+        assert_eq!(stats.stale_hits, 1);
+        assert_eq!(stats.stale_if_error_hits, 0);
+        // The expired lookup is not counted until it is known whether it was served.
+        assert_eq!(stats.misses, 2);
+        assert_eq!(stats.entries, 3);
+    }
+
+    #[test]
+    fn counts_expired_lookups_once() {
+        let cache = cache(10);
+        let key = cache.key(35.0, 139.0, 1);
+        insert_aged(&cache, key, Duration::from_secs(40));
+
+        assert!(matches!(cache.get(&key), Lookup::Expired(_)));
+        cache.record_expired(true);
+        assert!(matches!(cache.get(&key), Lookup::Expired(_)));
+        cache.record_expired(false);
+
+        let stats = cache.stats();
+        assert_eq!(stats.stale_if_error_hits, 1);
+        assert_eq!(stats.misses, 1);
+        assert_eq!(stats.hits + stats.stale_hits, 0);
+    }
+
+    #[test]
+    fn evicts_the_oldest_entry_when_full() {
+        let cache = cache(2);
+        let key = |limit| cache.key(35.0, 139.0, limit);
+        insert_aged(&cache, key(1), Duration::from_secs(3));
+        insert_aged(&cache, key(2), Duration::from_secs(2));
+        // Refreshing an entry makes it the newest.
+        insert_aged(&cache, key(1), Duration::from_secs(1));
+        cache.insert(key(3), vec![station(3)]);
+
+        assert!(is_cached(&cache, &key(1)));
+        assert!(!is_cached(&cache, &key(2)));
+        assert!(is_cached(&cache, &key(3)));
+
+        // Replacing an entry never evicts another one.
+        cache.insert(key(3), vec![station(3)]);
+        assert_eq!(cache.stats().entries, 2);
+    }
+
+    #[test]
+    fn keeps_the_eviction_order_bounded() {
+        let cache = cache(2);
+        let key = |limit| cache.key(35.0, 139.0, limit);
+        for _ in 0..100 {
+            cache.insert(key(1), vec![station(1)]);
+            cache.insert(key(2), vec![station(2)]);
+        }
+        assert!(cache.entries.lock().unwrap().order.len() <= 4);
+
+        cache.insert(key(3), vec![station(3)]);
+        assert!(!is_cached(&cache, &key(1)));
+        assert!(is_cached(&cache, &key(2)));
+        assert!(is_cached(&cache, &key(3)));
+    }
+
+    #[test]
+    fn zero_capacity_disables_the_cache() {
+        let cache = cache(0);
+        let key = cache.key(35.0, 139.0, 1);
+        cache.insert(key, vec![station(1)]);
+        assert!(!cache.is_enabled());
+        assert!(matches!(cache.get(&key), Lookup::Miss));
+    }
+
+    #[test]
+    fn claims_each_refresh_once() {
+        let cache = cache(10);
+        let key = cache.key(35.0, 139.0, 1);
+        assert!(cache.begin_refresh(key));
+        assert!(!cache.begin_refresh(key));
+        cache.end_refresh(&key);
+        assert!(cache.begin_refresh(key));
+    }
+}
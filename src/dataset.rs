@@ -0,0 +1,287 @@
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+    path::Path,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use prost::Message;
+use serde::Serialize;
+
+use crate::{
+    error::AppError,
+    spatial::SpatialIndex,
+    station_api::{Line, Station},
+};
+
+/// Version of the snapshot file format this build reads and writes.
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+/// On-disk snapshot of StationAPI data, encoded as protobuf.
+#[derive(Clone, PartialEq, Message)]
+pub struct Snapshot {
+    #[prost(uint32, tag = "1")]
+    pub version: u32,
+    /// Unix time in seconds when the snapshot was taken.
+    #[prost(uint64, tag = "2")]
+    pub created_at: u64,
+    #[prost(message, repeated, tag = "3")]
+    pub stations: Vec<Station>,
+    /// Lines with their stops in running order. May be empty, in which case
+    /// lines are derived from `Station::lines`.
+    #[prost(message, repeated, tag = "4")]
+    pub lines: Vec<SnapshotLine>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct SnapshotLine {
+    #[prost(message, optional, tag = "1")]
+    pub line: Option<Line>,
+    #[prost(uint32, repeated, tag = "2")]
+    pub station_ids: Vec<u32>,
+}
+
+struct LineEntry {
+    line: Line,
+    station_ids: Vec<u32>,
+}
+
+/// Station data held in memory, answering the same questions as StationAPI.
+pub struct Dataset {
+    created_at: u64,
+    stations: Vec<Station>,
+    by_id: HashMap<u32, usize>,
+    /// One station per `group_id`: the snapshot holds a record per station and
+    /// line, but coordinate lookups should return each physical station once.
+    representatives: Vec<usize>,
+    /// Spatial index over `representatives`.
+    index: SpatialIndex,
+    lines: Vec<LineEntry>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DatasetStatus {
+    pub created_at: u64,
+    pub stations: usize,
+    pub lines: usize,
+}
+
+impl Dataset {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Box<dyn Error>> {
+        let bytes = std::fs::read(path)?;
+        let snapshot = Snapshot::decode(bytes.as_slice())?;
+        if snapshot.version != SNAPSHOT_VERSION {
+            return Err(format!(
+                "Unsupported snapshot version {}; expected {}.",
+                snapshot.version, SNAPSHOT_VERSION
+            )
+            .into());
+        }
+        Ok(Self::from_snapshot(snapshot))
+    }
+
+    pub fn from_snapshot(snapshot: Snapshot) -> Self {
+        let by_id = snapshot
+            .stations
+            .iter()
+            .enumerate()
+            .map(|(i, s)| (s.id, i))
+            .collect();
+        let mut groups = HashSet::new();
+        let representatives = (0..snapshot.stations.len())
+            .filter(|&i| groups.insert(snapshot.stations[i].group_id))
+            .collect::<Vec<_>>();
+        let index = SpatialIndex::new(representatives.iter().map(|&i| {
+            let station = &snapshot.stations[i];
+            (station.latitude, station.longitude)
+        }));
+
+        let lines = if snapshot.lines.is_empty() {
+            derive_lines(&snapshot.stations)
+        } else {
+            snapshot
+                .lines
+                .into_iter()
+                .filter_map(|l| {
+                    Some(LineEntry {
+                        line: l.line?,
+                        station_ids: l.station_ids,
+                    })
+                })
+                .collect()
+        };
+
+        Self {
+            created_at: snapshot.created_at,
+            stations: snapshot.stations,
+            by_id,
+            representatives,
+            index,
+            lines,
+        }
+    }
+
+    /// An empty dataset that every hybrid lookup treats as stale.
+    pub fn empty() -> Self {
+        Self::from_snapshot(Snapshot {
+            version: SNAPSHOT_VERSION,
+            ..Default::default()
+        })
+    }
+
+    pub fn created_at(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(self.created_at)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stations.is_empty()
+    }
+
+    pub fn contains(&self, id: u32) -> bool {
+        self.by_id.contains_key(&id)
+    }
+
+    pub fn line_ids(&self) -> Vec<u32> {
+        self.lines.iter().map(|l| l.line.id).collect()
+    }
+
+    pub fn status(&self) -> DatasetStatus {
+        DatasetStatus {
+            created_at: self.created_at,
+            stations: self.stations.len(),
+            lines: self.lines.len(),
+        }
+    }
+
+    /// Returns up to `limit` stations within `radius` metres of the given point, nearest first.
+    pub fn nearby(
+        &self,
+        latitude: f64,
+        longitude: f64,
+        limit: u32,
+        radius: Option<f64>,
+    ) -> Result<Vec<Station>, AppError> {
+        let max_distance = radius.unwrap_or(f64::INFINITY);
+        let stations = self
+            .index
+            .nearest(latitude, longitude, limit as usize, max_distance)
+            .into_iter()
+            .map(|i| self.stations[self.representatives[i]].clone())
+            .collect::<Vec<_>>();
+        if stations.is_empty() {
+            return Err(match radius {
+                Some(radius) if !self.is_empty() => AppError::NoStationWithinRadius(radius),
+                _ => AppError::StationNotFound,
+            });
+        }
+        Ok(stations)
+    }
+
+    pub fn station(&self, id: u32) -> Result<Station, AppError> {
+        self.by_id
+            .get(&id)
+            .map(|&i| self.stations[i].clone())
+            .ok_or(AppError::StationNotFound)
+    }
+
+    pub fn stations(&self, ids: &[u32]) -> Result<Vec<Station>, AppError> {
+        let stations = ids
+            .iter()
+            .filter_map(|id| self.by_id.get(id))
+            .map(|&i| self.stations[i].clone())
+            .collect::<Vec<_>>();
+        if stations.is_empty() {
+            return Err(AppError::StationNotFound);
+        }
+        Ok(stations)
+    }
+
+    /// Case-insensitive substring match on the Japanese, katakana and romanized names,
+    /// returning each station group once.
+    pub fn search_stations(&self, query: &str, limit: u32) -> Vec<Station> {
+        let query = query.to_lowercase();
+        self.representatives
+            .iter()
+            .map(|&i| &self.stations[i])
+            .filter(|s| {
+                [
+                    Some(s.name.as_str()),
+                    Some(s.name_katakana.as_str()),
+                    s.name_roman.as_deref(),
+                ]
+                .into_iter()
+                .flatten()
+                .any(|name| name.to_lowercase().contains(&query))
+            })
+            .take(limit as usize)
+            .cloned()
+            .collect()
+    }
+
+    pub fn line(&self, line_id: u32) -> Result<Line, AppError> {
+        self.line_entry(line_id).map(|l| l.line.clone())
+    }
+
+    /// A line without stops is reported as unknown, as StationAPI does.
+    pub fn line_stations(&self, line_id: u32) -> Result<Vec<Station>, AppError> {
+        let entry = self.line_entry(line_id)?;
+        let stations = entry
+            .station_ids
+            .iter()
+            .filter_map(|id| self.by_id.get(id))
+            .map(|&i| self.stations[i].clone())
+            .collect::<Vec<_>>();
+        if stations.is_empty() {
+            return Err(AppError::LineNotFound);
+        }
+        Ok(stations)
+    }
+
+    /// Case-insensitive substring match on the short, katakana, full and romanized names.
+    pub fn search_lines(&self, query: &str, limit: u32) -> Vec<Line> {
+        let query = query.to_lowercase();
+        self.lines
+            .iter()
+            .map(|l| &l.line)
+            .filter(|l| {
+                [
+                    Some(l.name_short.as_str()),
+                    Some(l.name_katakana.as_str()),
+                    Some(l.name_full.as_str()),
+                    l.name_roman.as_deref(),
+                ]
+                .into_iter()
+                .flatten()
+                .any(|name| name.to_lowercase().contains(&query))
+            })
+            .take(limit as usize)
+            .cloned()
+            .collect()
+    }
+
+    fn line_entry(&self, line_id: u32) -> Result<&LineEntry, AppError> {
+        self.lines
+            .iter()
+            .find(|l| l.line.id == line_id)
+            .ok_or(AppError::LineNotFound)
+    }
+}
+
+/// Builds the line list from the lines each station is on, in file order.
+fn derive_lines(stations: &[Station]) -> Vec<LineEntry> {
+    let mut lines: Vec<LineEntry> = Vec::new();
+    let mut index = HashMap::new();
+    for station in stations {
+        for line in &station.lines {
+            let i = *index.entry(line.id).or_insert_with(|| {
+                lines.push(LineEntry {
+                    line: line.clone(),
+                    station_ids: Vec::new(),
+                });
+                lines.len() - 1
+            });
+            lines[i].station_ids.push(station.id);
+        }
+    }
+    lines
+}
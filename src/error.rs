@@ -0,0 +1,133 @@
+use std::{error::Error as _, fmt, time::Duration};
+
+use axum::{
+    http::{header, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+
+use crate::breaker::CircuitOpen;
+
+#[derive(Debug)]
+pub enum AppError {
+    /// A required query parameter is absent.
+    MissingParameter(&'static str),
+    /// A query parameter is present but malformed or out of range.
+    InvalidParameter(String),
+    /// StationAPI answered, but there is no station to return.
+    StationNotFound,
+    /// StationAPI answered, but there is no line to return.
+    LineNotFound,
+    /// There are stations, but none within the requested radius (in metres).
+    NoStationWithinRadius(f64),
+    /// StationAPI returned an error status.
+    Upstream(Box<tonic::Status>),
+    /// StationAPI did not answer in time.
+    UpstreamTimeout,
+    /// StationAPI has been failing, so we did not even try; retry after the given time.
+    CircuitOpen(Duration),
+}
+
+#[derive(Debug, Serialize)]
+struct ErrorBody {
+    error: &'static str,
+    message: String,
+}
+
+impl AppError {
+    fn status(&self) -> StatusCode {
+        match self {
+            AppError::MissingParameter(_) | AppError::InvalidParameter(_) => {
+                StatusCode::BAD_REQUEST
+            }
+            AppError::StationNotFound
+            | AppError::LineNotFound
+            | AppError::NoStationWithinRadius(_) => StatusCode::NOT_FOUND,
+            AppError::Upstream(_) => StatusCode::BAD_GATEWAY,
+            AppError::UpstreamTimeout => StatusCode::GATEWAY_TIMEOUT,
+            AppError::CircuitOpen(_) => StatusCode::SERVICE_UNAVAILABLE,
+        }
+    }
+
+    /// Stable identifier for clients to match on.
+    fn code(&self) -> &'static str {
+        match self {
+            AppError::MissingParameter(_) => "missing_parameter",
+            AppError::InvalidParameter(_) => "invalid_parameter",
+            AppError::StationNotFound => "station_not_found",
+            AppError::LineNotFound => "line_not_found",
+            AppError::NoStationWithinRadius(_) => "no_station_within_radius",
+            AppError::Upstream(_) => "upstream_error",
+            AppError::UpstreamTimeout => "upstream_timeout",
+            AppError::CircuitOpen(_) => "circuit_open",
+        }
+    }
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AppError::MissingParameter(name) => {
+                write!(f, "The parameter `{}` isn't present.", name)
+            }
+            AppError::InvalidParameter(message) => f.write_str(message),
+            AppError::StationNotFound => f.write_str("No station was found."),
+            AppError::LineNotFound => f.write_str("No line was found."),
+            AppError::NoStationWithinRadius(radius) => {
+                write!(f, "No station was found within {} m.", radius)
+            }
+            AppError::Upstream(status) => write!(
+                f,
+                "StationAPI returned an error: {:?}: {}",
+                status.code(),
+                status.message()
+            ),
+            AppError::UpstreamTimeout => f.write_str("StationAPI did not respond in time."),
+            AppError::CircuitOpen(_) => {
+                f.write_str("StationAPI is unavailable at the moment. Please retry later.")
+            }
+        }
+    }
+}
+
+impl std::error::Error for AppError {}
+
+impl From<tonic::Status> for AppError {
+    fn from(status: tonic::Status) -> Self {
+        if let Some(open) = status
+            .source()
+            .and_then(|e| e.downcast_ref::<CircuitOpen>())
+        {
+            return AppError::CircuitOpen(open.retry_after);
+        }
+
+        match status.code() {
+            tonic::Code::DeadlineExceeded => AppError::UpstreamTimeout,
+            _ => AppError::Upstream(Box::new(status)),
+        }
+    }
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        let status = self.status();
+        if status.is_server_error() {
+            tracing::error!("{}", self);
+        }
+
+        let body = ErrorBody {
+            error: self.code(),
+            message: self.to_string(),
+        };
+        let mut response = (status, Json(body)).into_response();
+
+        if let AppError::CircuitOpen(retry_after) = self {
+            let secs = retry_after.as_secs_f64().ceil().max(1.0) as u64;
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, HeaderValue::from(secs));
+        }
+        response
+    }
+}
@@ -0,0 +1,20 @@
+use crate::station_api::Station;
+
+/// Mean earth radius in metres.
+pub const EARTH_RADIUS_M: f64 = 6_371_008.8;
+
+/// Great-circle distance in metres between two points given in degrees.
+pub fn haversine_distance(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+    let (phi1, phi2) = (lat1.to_radians(), lat2.to_radians());
+    let d_phi = (lat2 - lat1).to_radians();
+    let d_lambda = (lon2 - lon1).to_radians();
+
+    let a = (d_phi / 2.0).sin().powi(2) + phi1.cos() * phi2.cos() * (d_lambda / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_M * a.sqrt().asin()
+}
+
+/// Sorts stations by their distance from the given point, nearest first.
+pub fn sort_by_distance(stations: &mut [Station], latitude: f64, longitude: f64) {
+    let distance = |s: &Station| haversine_distance(latitude, longitude, s.latitude, s.longitude);
+    stations.sort_by(|a, b| distance(a).total_cmp(&distance(b)));
+}
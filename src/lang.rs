@@ -0,0 +1,127 @@
+use axum::http::{header, HeaderMap};
+
+use crate::{
+    error::AppError,
+    station_api::{Company, Line, Station},
+};
+
+/// Language the plain-text output is written in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Language {
+    Ja,
+    JaKana,
+    En,
+    Zh,
+    Ko,
+}
+
+impl Language {
+    /// Parses a language tag such as `ja-Kana` or `en-US`, ignoring case.
+    pub fn parse(tag: &str) -> Option<Self> {
+        let tag = tag.trim().to_ascii_lowercase();
+        if tag == "ja-kana" || tag == "ja-hrkt" {
+            return Some(Language::JaKana);
+        }
+        match tag.split('-').next() {
+            Some("ja") => Some(Language::Ja),
+            Some("en") => Some(Language::En),
+            Some("zh") => Some(Language::Zh),
+            Some("ko") => Some(Language::Ko),
+            _ => None,
+        }
+    }
+
+    /// `?lang=` wins over the legacy `?en=`, which wins over `Accept-Language`.
+    /// Japanese is the default.
+    pub fn negotiate(
+        lang: Option<&str>,
+        en: Option<bool>,
+        headers: &HeaderMap,
+    ) -> Result<Self, AppError> {
+        if let Some(lang) = lang {
+            return Language::parse(lang).ok_or_else(|| {
+                AppError::InvalidParameter(
+                    "The parameter `lang` must be one of ja, ja-Kana, en, zh or ko.".to_string(),
+                )
+            });
+        }
+        match en {
+            Some(true) => return Ok(Language::En),
+            // Legacy clients send `en=false` to mean Japanese, whatever their locale.
+            Some(false) => return Ok(Language::Ja),
+            None => {}
+        }
+        Ok(from_accept_language(headers).unwrap_or(Language::Ja))
+    }
+
+    /// Languages to try, in order, when a name is missing in this one.
+    fn fallbacks(self) -> &'static [Language] {
+        match self {
+            Language::Ja => &[Language::Ja],
+            Language::JaKana => &[Language::JaKana, Language::Ja],
+            Language::En => &[Language::En, Language::Ja],
+            Language::Zh => &[Language::Zh, Language::En, Language::Ja],
+            Language::Ko => &[Language::Ko, Language::En, Language::Ja],
+        }
+    }
+
+    /// Returns the first non-empty name along the fallback chain.
+    fn pick<'a>(self, name: impl Fn(Language) -> Option<&'a str>) -> &'a str {
+        self.fallbacks()
+            .iter()
+            .filter_map(|&l| name(l))
+            .find(|n| !n.is_empty())
+            .unwrap_or("")
+    }
+}
+
+/// Picks the supported language with the highest quality value, if any.
+fn from_accept_language(headers: &HeaderMap) -> Option<Language> {
+    let mut candidates = headers
+        .get_all(header::ACCEPT_LANGUAGE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .filter_map(|item| {
+            let mut parts = item.split(';');
+            let lang = Language::parse(parts.next()?)?;
+            let q = parts
+                .find_map(|p| p.trim().strip_prefix("q="))
+                .map_or(Some(1.0), |q| q.parse::<f32>().ok())?;
+            (q > 0.0).then_some((lang, q))
+        })
+        .collect::<Vec<_>>();
+
+    // Stable, so equally weighted languages keep the client's order.
+    candidates.sort_by(|a, b| b.1.total_cmp(&a.1));
+    candidates.first().map(|(lang, _)| *lang)
+}
+
+pub fn station_name(station: &Station, lang: Language) -> &str {
+    lang.pick(|l| match l {
+        Language::Ja => Some(station.name.as_str()),
+        Language::JaKana => Some(station.name_katakana.as_str()),
+        Language::En => station.name_roman.as_deref(),
+        Language::Zh => station.name_chinese.as_deref(),
+        Language::Ko => station.name_korean.as_deref(),
+    })
+}
+
+pub fn line_name(line: &Line, lang: Language) -> &str {
+    lang.pick(|l| match l {
+        Language::Ja => Some(line.name_short.as_str()),
+        Language::JaKana => Some(line.name_katakana.as_str()),
+        Language::En => line.name_roman.as_deref(),
+        Language::Zh => line.name_chinese.as_deref(),
+        Language::Ko => line.name_korean.as_deref(),
+    })
+}
+
+pub fn company_name(company: &Company, lang: Language) -> &str {
+    lang.pick(|l| match l {
+        Language::Ja => Some(company.name_short.as_str()),
+        Language::JaKana => Some(company.name_katakana.as_str()),
+        Language::En => Some(company.name_english_short.as_str()),
+        Language::Zh | Language::Ko => None,
+    })
+}
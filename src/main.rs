@@ -1,72 +1,337 @@
 use std::{
     env::{self, VarError},
+    fmt::Debug,
     net::{AddrParseError, SocketAddr},
+    path::PathBuf,
+    str::FromStr,
+    sync::Arc,
+    time::Duration,
 };
 
-use axum::{extract::Query, routing::get, Router};
-use hyper_tls::HttpsConnector;
-use serde::Deserialize;
-use station_api::Station;
-use tonic_web::GrpcWebClientLayer;
+use axum::{
+    extract::{
+        rejection::{PathRejection, QueryRejection},
+        Path, Query, State,
+    },
+    http::{HeaderMap, HeaderValue, Request, StatusCode},
+    middleware::{self, Next},
+    response::Response,
+    routing::get,
+    Json, Router,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    breaker::CircuitBreaker,
+    cache::{CacheStats, CacheStatus, CacheTtl, CellKey, Lookup, NearbyCache, CACHE_STATUS_HEADER},
+    dataset::{Dataset, DatasetStatus},
+    error::AppError,
+    geo::{haversine_distance, sort_by_distance},
+    lang::Language,
+    privacy::{Privacy, PrivacyMode},
+    response::Format,
+    source::{Hybrid, Source, SourceKind},
+    station_api::Station,
+    upstream::UpstreamStatus,
+};
 
-use crate::station_api::station_api_client::StationApiClient;
+mod access_log;
+mod breaker;
+mod cache;
+mod dataset;
+mod error;
+mod geo;
+mod lang;
+mod metrics;
+mod privacy;
+mod response;
+mod snapshot;
+mod source;
+mod spatial;
+mod telemetry;
+mod upstream;
 
 pub mod station_api {
     tonic::include_proto!("app.trainlcd.grpc");
 }
 
+/// Debug header naming the StationAPI upstream that served the response, sent only
+/// when `SAPI_DEBUG_HEADERS` is set since it reveals internal origins.
+const UPSTREAM_HEADER: &str = "x-upstream";
+
+/// Upper bound for `limit` on `/nearby`, regardless of what the client asks for.
+const MAX_NEARBY_LIMIT: u32 = 20;
+
+/// Upper bound for `limit` on `/search` and `/lines`; also the default when it is omitted.
+const MAX_SEARCH_LIMIT: u32 = 20;
+
+/// Upper bound for the number of ids in a single `/stations?ids=` lookup.
+const MAX_STATION_IDS: usize = 100;
+
+#[derive(Clone)]
+struct AppState {
+    source: Source,
+    cache: Arc<NearbyCache>,
+    privacy: Privacy,
+    ready_probe: upstream::ReadyProbe,
+}
+
 #[derive(Debug, Deserialize)]
 #[allow(dead_code)]
 struct Params {
     latitude: Option<f64>,
     longitude: Option<f64>,
     en: Option<bool>,
+    lang: Option<String>,
+    format: Option<String>,
+    limit: Option<u32>,
+    /// Only return stations within this many metres of the queried point.
+    radius: Option<f64>,
+}
+
+/// Query parameters for lookups that only choose how the result is rendered.
+#[derive(Debug, Deserialize)]
+struct OutputParams {
+    en: Option<bool>,
+    lang: Option<String>,
+    format: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct StationsParams {
+    /// Comma-separated station ids, e.g. `1130101,1130102`.
+    ids: Option<String>,
+    en: Option<bool>,
+    lang: Option<String>,
+    format: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SearchParams {
+    q: Option<String>,
+    limit: Option<u32>,
+    en: Option<bool>,
+    lang: Option<String>,
+    format: Option<String>,
+}
+
+impl SearchParams {
+    fn query(&self) -> Result<&str, AppError> {
+        self.q
+            .as_deref()
+            .map(str::trim)
+            .filter(|q| !q.is_empty())
+            .ok_or(AppError::MissingParameter("q"))
+    }
+
+    fn limit(&self) -> Result<u32, AppError> {
+        match self.limit {
+            Some(0) => Err(AppError::InvalidParameter(
+                "The parameter `limit` must be greater than 0.".to_string(),
+            )),
+            limit => Ok(limit.unwrap_or(MAX_SEARCH_LIMIT).min(MAX_SEARCH_LIMIT)),
+        }
+    }
 }
 
 #[tokio::main]
 async fn main() {
-    tracing_subscriber::fmt::init();
     dotenv::from_filename(".env.local").ok();
+    telemetry::init();
+
+    let args = env::args().skip(1).collect::<Vec<_>>();
+    if args.first().map(String::as_str) == Some("snapshot") {
+        let code = run_snapshot(&args[1..]).await;
+        telemetry::shutdown().await;
+        std::process::exit(code);
+    }
+
+    let source = match fetch_env("DATA_SOURCE", SourceKind::Remote) {
+        SourceKind::Remote => Source::Remote(build_client()),
+        SourceKind::Local => Source::Local(Arc::new(load_dataset())),
+        SourceKind::Hybrid => Source::Hybrid(build_hybrid()),
+    };
+    let cache = NearbyCache::new(
+        fetch_env("CACHE_GRID_DEGREES", 0.001),
+        CacheTtl {
+            fresh: Duration::from_secs(fetch_env("CACHE_TTL_SECS", 30)),
+            stale_while_revalidate: Duration::from_secs(fetch_env(
+                "CACHE_STALE_WHILE_REVALIDATE_SECS",
+                30,
+            )),
+            stale_if_error: Duration::from_secs(fetch_env("CACHE_STALE_IF_ERROR_SECS", 3600)),
+        },
+        fetch_env("CACHE_CAPACITY", 10_000),
+        fetch_env("CACHE_EXTRA_CANDIDATES", 5),
+    );
+    let cache = Arc::new(cache);
+    metrics::METRICS.register_cache(cache.clone());
+    let privacy = Privacy {
+        mode: fetch_env("PRIVACY_MODE", PrivacyMode::Off),
+        upstream: fetch_env("PRIVACY_UPSTREAM", false),
+    };
+    let state = AppState {
+        source,
+        cache,
+        privacy,
+        ready_probe: upstream::ReadyProbe {
+            max_age: Duration::from_secs(fetch_env("READY_PROBE_CACHE_SECS", 5)),
+            timeout: Duration::from_millis(fetch_env("READY_PROBE_TIMEOUT_MS", 1_000)),
+        },
+    };
 
     let addr = fetch_addr().unwrap();
-    let app = Router::new().route("/nearby", get(nearby));
+    let mut app = Router::new()
+        .route("/nearby", get(nearby))
+        .route("/stations", get(stations))
+        .route("/stations/:id", get(station))
+        .route("/search", get(search))
+        .route("/lines", get(lines))
+        .route("/lines/:line_id", get(line))
+        .route("/lines/:line_id/stations", get(line_stations))
+        .route("/status", get(status))
+        .route("/metrics", get(metrics::render))
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz));
+    if fetch_env("SAPI_DEBUG_HEADERS", false) {
+        app = app.layer(middleware::from_fn(upstream_header));
+    }
+    let app = app
+        .layer(middleware::from_fn(metrics::track))
+        .layer(middleware::from_fn(telemetry::trace))
+        .layer(middleware::from_fn_with_state(privacy, access_log::log))
+        .with_state(state);
     axum::Server::bind(&addr)
         .serve(app.into_make_service())
+        .with_graceful_shutdown(shutdown_signal())
         .await
         .unwrap();
+    telemetry::shutdown().await;
 }
 
-async fn fetch_nearby(
-    latitude: f64,
-    longitude: f64,
-) -> Result<Station, Box<dyn std::error::Error>> {
-    let sapi_url = std::env::var("SAPI_URL").expect("SAPI_URL must be set.");
+/// Resolves on Ctrl-C or, on Unix, SIGTERM from the orchestrator.
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("Failed to listen for Ctrl-C.");
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("Failed to listen for SIGTERM.")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
 
-    let https = HttpsConnector::new();
-    let client = hyper::Client::builder().build(https);
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+}
 
-    let svc = tower::ServiceBuilder::new()
-        .layer(GrpcWebClientLayer::new())
-        .service(client);
+/// `thinner snapshot <path> [<line id>...]`: exports StationAPI to a dataset file.
+///
+/// Returns the process exit code.
+async fn run_snapshot(args: &[String]) -> i32 {
+    let Some((path, seeds)) = args.split_first() else {
+        eprintln!("Usage: thinner snapshot <path> [<line id>...]");
+        return 2;
+    };
+    let mut seed_line_ids = Vec::with_capacity(seeds.len());
+    for id in seeds {
+        let Ok(id) = id.parse::<u32>() else {
+            eprintln!("`{}` is not a line id.", id);
+            return 2;
+        };
+        seed_line_ids.push(id);
+    }
 
-    let mut client = StationApiClient::with_origin(svc, sapi_url.try_into()?);
+    let client = build_client();
+    let result = match snapshot::take(&client, &seed_line_ids).await {
+        Ok(snapshot) => snapshot::write(&snapshot, path),
+        Err(e) => Err(e),
+    };
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("Failed to write the snapshot to {}: {}", path, e);
+            1
+        }
+    }
+}
 
-    let request = tonic::Request::new(station_api::GetStationByCoordinatesRequest {
-        latitude,
-        longitude,
-        limit: Some(1),
-    });
+/// Sets up the StationAPI client from `SAPI_*` settings and starts its health checks.
+fn build_client() -> upstream::Client {
+    let sapi_url = env::var("SAPI_URL").expect("SAPI_URL must be set.");
+    let policy = upstream::CallPolicy {
+        timeout: Duration::from_millis(fetch_env("SAPI_TIMEOUT_MS", 10_000)),
+        max_retries: fetch_env("SAPI_MAX_RETRIES", 2),
+        backoff_base: Duration::from_millis(fetch_env("SAPI_RETRY_BACKOFF_MS", 100)),
+        backoff_max: Duration::from_millis(fetch_env("SAPI_RETRY_BACKOFF_MAX_MS", 2_000)),
+    };
+    let breaker_threshold = fetch_env("SAPI_BREAKER_THRESHOLD", 5);
+    let breaker_open_for = Duration::from_secs(fetch_env("SAPI_BREAKER_OPEN_SECS", 30));
+    let transport = fetch_env("SAPI_TRANSPORT", upstream::TransportKind::GrpcWeb);
+    let client = upstream::build_client(&sapi_url, transport, policy, || {
+        CircuitBreaker::new(breaker_threshold, breaker_open_for)
+    })
+    .unwrap_or_else(|e| panic!("Failed to set up the StationAPI client: {}", e));
+
+    let health_check_interval = fetch_env("SAPI_HEALTH_CHECK_SECS", 10);
+    if health_check_interval > 0 {
+        client.spawn_health_checks(Duration::from_secs(health_check_interval));
+    }
+    client
+}
 
-    let response = client.get_stations_by_coordinates(request).await?;
+/// Loads the local station dataset from `DATASET_PATH`.
+fn load_dataset() -> Dataset {
+    let path = env::var("DATASET_PATH").expect("DATASET_PATH must be set for a local data source.");
+    let dataset = Dataset::load(&path)
+        .unwrap_or_else(|e| panic!("Failed to load the dataset from {}: {}", path, e));
+    let status = dataset.status();
+    tracing::info!(
+        "Loaded {} stations and {} lines from {}",
+        status.stations,
+        status.lines,
+        path
+    );
+    dataset
+}
+
+/// Sets up hybrid mode; a missing dataset is fetched in the background while
+/// StationAPI answers everything.
+fn build_hybrid() -> Arc<Hybrid> {
+    let path = env::var("DATASET_PATH").ok();
+    let dataset = match &path {
+        Some(path) => Dataset::load(path).unwrap_or_else(|e| {
+            tracing::warn!("Starting without a local dataset: {}", e);
+            Dataset::empty()
+        }),
+        None => Dataset::empty(),
+    };
+    let hybrid = Hybrid::new(
+        build_client(),
+        dataset,
+        Duration::from_secs(fetch_env("DATASET_MAX_AGE_SECS", 86_400)),
+        path.map(PathBuf::from),
+    );
 
-    Ok(response.into_inner().stations[0].clone())
+    let refresh_interval = fetch_env("DATASET_REFRESH_SECS", 21_600);
+    if refresh_interval > 0 {
+        hybrid.spawn_refresh(Duration::from_secs(refresh_interval));
+    }
+    hybrid
 }
 
 fn fetch_port() -> u16 {
     match env::var("PORT") {
         Ok(s) => s.parse().expect("Failed to parse $PORT"),
         Err(env::VarError::NotPresent) => {
-            println!("$PORT is not set. Falling back to 3000.");
+            tracing::info!("$PORT is not set. Falling back to 3000.");
             3000
         }
         Err(VarError::NotUnicode(_)) => panic!("$PORT should be written in Unicode."),
@@ -79,39 +344,345 @@ fn fetch_addr() -> Result<SocketAddr, AddrParseError> {
         Ok(s) => format!("{}:{}", s, port).parse(),
         Err(env::VarError::NotPresent) => {
             let fallback_host = format!("[::1]:{}", port);
-            println!("$HOST is not set. Falling back to {}.", fallback_host);
+            tracing::info!("$HOST is not set. Falling back to {}.", fallback_host);
             fallback_host.parse()
         }
         Err(VarError::NotUnicode(_)) => panic!("$HOST should be written in Unicode."),
     }
 }
 
-async fn nearby(Query(params): Query<Params>) -> String {
-    let Some(lat) = params.latitude else {
-        return "ERROR! The parameter `latitude` isn't present.".to_string();
+/// Reports which StationAPI upstream answered the request, if any did.
+async fn upstream_header<B>(request: Request<B>, next: Next<B>) -> Response {
+    let (served_by, mut response) = upstream::track_served_by(next.run(request)).await;
+    if let Some(value) = served_by.and_then(|s| HeaderValue::from_str(&s).ok()) {
+        response.headers_mut().insert(UPSTREAM_HEADER, value);
+    }
+    response
+}
+
+/// Reads an optional tuning knob from the environment, falling back to `default`.
+fn fetch_env<T>(name: &str, default: T) -> T
+where
+    T: FromStr,
+    T::Err: Debug,
+{
+    match env::var(name) {
+        Ok(s) => s
+            .parse()
+            .unwrap_or_else(|e| panic!("Failed to parse ${}: {:?}", name, e)),
+        Err(env::VarError::NotPresent) => default,
+        Err(VarError::NotUnicode(_)) => panic!("${} should be written in Unicode.", name),
+    }
+}
+
+fn validate_coordinates(latitude: f64, longitude: f64) -> Result<(), AppError> {
+    if !(-90.0..=90.0).contains(&latitude) {
+        return Err(AppError::InvalidParameter(
+            "The parameter `latitude` must be between -90 and 90.".to_string(),
+        ));
+    }
+    if !(-180.0..=180.0).contains(&longitude) {
+        return Err(AppError::InvalidParameter(
+            "The parameter `longitude` must be between -180 and 180.".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Looks up the stations around a point through the grid cache.
+///
+/// On a miss the centre of the grid cell is queried for a few more stations than
+/// asked for, so every point in the cell shares the same entry; the result is then
+/// ranked by distance from the actual point and cut down to `limit`.
+/// Stale entries are served while a background task refreshes them, and expired
+/// ones are still served if StationAPI is failing.
+async fn fetch_nearby_cached(
+    state: &AppState,
+    latitude: f64,
+    longitude: f64,
+    limit: u32,
+    radius: Option<f64>,
+) -> Result<(Vec<Station>, Option<CacheStatus>), AppError> {
+    if !state.cache.is_enabled() || state.source.serves_locally() {
+        let (lat, lon) = state.privacy.upstream(latitude, longitude);
+        let mut stations = state.source.fetch_nearby(lat, lon, limit, radius).await?;
+        sort_by_distance(&mut stations, latitude, longitude);
+        return Ok((stations, None));
+    }
+
+    // Precise coordinates never make it into the cache, only the coarsened ones.
+    let (lat, lon) = state.privacy.reduce(latitude, longitude);
+    let key = state.cache.key(lat, lon, limit);
+    let (mut stations, status) = match state.cache.get(&key) {
+        Lookup::Fresh(stations) => (stations.as_ref().clone(), CacheStatus::Hit),
+        Lookup::Stale(stations) => {
+            spawn_refresh(state.clone(), key);
+            (stations.as_ref().clone(), CacheStatus::Stale)
+        }
+        lookup => {
+            let (lat, lon) = state.cache.center(&key);
+            let result = state
+                .source
+                .fetch_nearby(lat, lon, state.cache.fetch_limit(&key), None)
+                .await;
+            let serve_expired = matches!(
+                result,
+                Err(AppError::Upstream(_) | AppError::UpstreamTimeout | AppError::CircuitOpen(_))
+            );
+            if let Lookup::Expired(_) = lookup {
+                state.cache.record_expired(serve_expired);
+            }
+
+            match result {
+                Ok(stations) => {
+                    state.cache.insert(key, stations.clone());
+                    (stations, CacheStatus::Miss)
+                }
+                Err(e) => match lookup {
+                    Lookup::Expired(stations) if serve_expired => {
+                        tracing::warn!("Serving a stale /nearby result: {}", e);
+                        (stations.as_ref().clone(), CacheStatus::StaleIfError)
+                    }
+                    _ => return Err(e),
+                },
+            }
+        }
     };
-    let Some(lon) = params.longitude else {
-        return "ERROR! The parameter `longitude` isn't present.".to_string();
+
+    sort_by_distance(&mut stations, latitude, longitude);
+    stations.truncate(limit as usize);
+    Ok((stations, Some(status)))
+}
+
+/// Refreshes a stale cache cell without making the current request wait for it.
+fn spawn_refresh(state: AppState, key: CellKey) {
+    if !state.cache.begin_refresh(key) {
+        return;
+    }
+
+    tokio::spawn(async move {
+        let (lat, lon) = state.cache.center(&key);
+        let limit = state.cache.fetch_limit(&key);
+        match state.source.fetch_nearby(lat, lon, limit, None).await {
+            Ok(stations) => state.cache.insert(key, stations),
+            Err(e) => tracing::warn!("Failed to refresh a stale /nearby result: {}", e),
+        }
+        state.cache.end_refresh(&key);
+    });
+}
+
+async fn nearby(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    params: Result<Query<Params>, QueryRejection>,
+) -> Result<Response, AppError> {
+    let Query(params) = params.map_err(|e| AppError::InvalidParameter(e.body_text()))?;
+    let lat = params
+        .latitude
+        .ok_or(AppError::MissingParameter("latitude"))?;
+    let lon = params
+        .longitude
+        .ok_or(AppError::MissingParameter("longitude"))?;
+    validate_coordinates(lat, lon)?;
+    if params.limit == Some(0) {
+        return Err(AppError::InvalidParameter(
+            "The parameter `limit` must be greater than 0.".to_string(),
+        ));
+    }
+    if params.radius.is_some_and(|r| !(r.is_finite() && r > 0.0)) {
+        return Err(AppError::InvalidParameter(
+            "The parameter `radius` must be a positive number of metres.".to_string(),
+        ));
+    }
+
+    let format = Format::negotiate(params.format.as_deref(), &headers);
+    let lang = Language::negotiate(params.lang.as_deref(), params.en, &headers)?;
+
+    // Without `limit` or `radius`, keep answering with the single nearest station as before.
+    if params.limit.is_none() && params.radius.is_none() {
+        let (stations, cache_status) = fetch_nearby_cached(&state, lat, lon, 1, None).await?;
+        let station = stations.first().ok_or(AppError::StationNotFound)?;
+        let response = response::render_station(format, station, Some((lat, lon)), lang);
+        return Ok(with_cache_status(response, cache_status));
+    }
+
+    // A local dataset answers radius queries from its index, so only an explicit
+    // `limit` bounds them; StationAPI has to be asked for a fixed number of stations.
+    let limit = match params.limit {
+        None if params.radius.is_some() && matches!(state.source, Source::Local(_)) => u32::MAX,
+        limit => limit.unwrap_or(MAX_NEARBY_LIMIT).min(MAX_NEARBY_LIMIT),
     };
+    let (mut stations, cache_status) =
+        fetch_nearby_cached(&state, lat, lon, limit, params.radius).await?;
 
-    let station = fetch_nearby(lat, lon).await.unwrap();
+    if let Some(radius) = params.radius {
+        stations.retain(|s| haversine_distance(lat, lon, s.latitude, s.longitude) <= radius);
+        if stations.is_empty() {
+            return Err(AppError::NoStationWithinRadius(radius));
+        }
+    }
 
-    let lines = station
-        .lines
-        .iter()
-        .map(|l| match params.en {
-            Some(true) => l.name_roman.clone().unwrap_or("".to_string()),
-            _ => l.name_short.clone(),
+    let response = response::render_stations(format, &stations, Some((lat, lon)), lang);
+    Ok(with_cache_status(response, cache_status))
+}
+
+fn with_cache_status(mut response: Response, status: Option<CacheStatus>) -> Response {
+    if let Some(status) = status {
+        response.headers_mut().insert(
+            CACHE_STATUS_HEADER,
+            HeaderValue::from_static(status.as_str()),
+        );
+    }
+    response
+}
+
+async fn station(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    id: Result<Path<u32>, PathRejection>,
+    params: Result<Query<OutputParams>, QueryRejection>,
+) -> Result<Response, AppError> {
+    let Path(id) = id.map_err(|e| AppError::InvalidParameter(e.body_text()))?;
+    let Query(params) = params.map_err(|e| AppError::InvalidParameter(e.body_text()))?;
+
+    let format = Format::negotiate(params.format.as_deref(), &headers);
+    let lang = Language::negotiate(params.lang.as_deref(), params.en, &headers)?;
+    let station = state.source.fetch_station(id).await?;
+    Ok(response::render_station(format, &station, None, lang))
+}
+
+fn parse_ids(ids: &str) -> Result<Vec<u32>, AppError> {
+    let ids = ids
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(|s| {
+            s.parse().map_err(|_| {
+                AppError::InvalidParameter(format!("`{}` is not a valid station id.", s))
+            })
         })
-        .collect::<Vec<_>>()
-        .join(", ");
-
-    match params.en {
-        Some(true) => format!(
-            "{}\n{}",
-            station.name_roman.unwrap_or("".to_string()),
-            lines
-        ),
-        _ => format!("{}\n{}", station.name, lines),
+        .collect::<Result<Vec<u32>, _>>()?;
+
+    if ids.is_empty() {
+        return Err(AppError::MissingParameter("ids"));
+    }
+    if ids.len() > MAX_STATION_IDS {
+        return Err(AppError::InvalidParameter(format!(
+            "The parameter `ids` accepts at most {} ids.",
+            MAX_STATION_IDS
+        )));
+    }
+    Ok(ids)
+}
+
+async fn stations(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    params: Result<Query<StationsParams>, QueryRejection>,
+) -> Result<Response, AppError> {
+    let Query(params) = params.map_err(|e| AppError::InvalidParameter(e.body_text()))?;
+    let ids = params
+        .ids
+        .as_deref()
+        .ok_or(AppError::MissingParameter("ids"))
+        .and_then(parse_ids)?;
+
+    let format = Format::negotiate(params.format.as_deref(), &headers);
+    let lang = Language::negotiate(params.lang.as_deref(), params.en, &headers)?;
+    let stations = state.source.fetch_stations(ids).await?;
+    Ok(response::render_stations(format, &stations, None, lang))
+}
+
+async fn search(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    params: Result<Query<SearchParams>, QueryRejection>,
+) -> Result<Response, AppError> {
+    let Query(params) = params.map_err(|e| AppError::InvalidParameter(e.body_text()))?;
+    let query = params.query()?;
+    let limit = params.limit()?;
+
+    let format = Format::negotiate(params.format.as_deref(), &headers);
+    let lang = Language::negotiate(params.lang.as_deref(), params.en, &headers)?;
+    let stations = state
+        .source
+        .search_stations(query.to_string(), limit)
+        .await?;
+    Ok(response::render_stations(format, &stations, None, lang))
+}
+
+async fn line_stations(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    line_id: Result<Path<u32>, PathRejection>,
+    params: Result<Query<OutputParams>, QueryRejection>,
+) -> Result<Response, AppError> {
+    let Path(line_id) = line_id.map_err(|e| AppError::InvalidParameter(e.body_text()))?;
+    let Query(params) = params.map_err(|e| AppError::InvalidParameter(e.body_text()))?;
+
+    let format = Format::negotiate(params.format.as_deref(), &headers);
+    let lang = Language::negotiate(params.lang.as_deref(), params.en, &headers)?;
+    let stations = state.source.fetch_line_stations(line_id).await?;
+    Ok(response::render_line_stations(format, &stations, lang))
+}
+
+async fn line(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    line_id: Result<Path<u32>, PathRejection>,
+    params: Result<Query<OutputParams>, QueryRejection>,
+) -> Result<Response, AppError> {
+    let Path(line_id) = line_id.map_err(|e| AppError::InvalidParameter(e.body_text()))?;
+    let Query(params) = params.map_err(|e| AppError::InvalidParameter(e.body_text()))?;
+
+    let format = Format::negotiate(params.format.as_deref(), &headers);
+    let lang = Language::negotiate(params.lang.as_deref(), params.en, &headers)?;
+    let line = state.source.fetch_line(line_id).await?;
+    Ok(response::render_line(format, &line, lang))
+}
+
+async fn lines(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    params: Result<Query<SearchParams>, QueryRejection>,
+) -> Result<Response, AppError> {
+    let Query(params) = params.map_err(|e| AppError::InvalidParameter(e.body_text()))?;
+    let query = params.query()?;
+    let limit = params.limit()?;
+
+    let format = Format::negotiate(params.format.as_deref(), &headers);
+    let lang = Language::negotiate(params.lang.as_deref(), params.en, &headers)?;
+    let lines = state.source.search_lines(query.to_string(), limit).await?;
+    Ok(response::render_lines(format, &lines, lang))
+}
+
+#[derive(Debug, Serialize)]
+struct Status {
+    cache: CacheStats,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    upstreams: Option<Vec<UpstreamStatus>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dataset: Option<DatasetStatus>,
+}
+
+async fn status(State(state): State<AppState>) -> Json<Status> {
+    Json(Status {
+        cache: state.cache.stats(),
+        upstreams: state.source.upstream_status(),
+        dataset: state.source.dataset_status(),
+    })
+}
+
+/// Liveness: the process is up and serving requests.
+async fn healthz() -> &'static str {
+    "ok"
+}
+
+/// Readiness: a local dataset is loaded or StationAPI answers a probe.
+async fn readyz(State(state): State<AppState>) -> (StatusCode, &'static str) {
+    if state.source.is_ready(state.ready_probe).await {
+        (StatusCode::OK, "ok")
+    } else {
+        (StatusCode::SERVICE_UNAVAILABLE, "unavailable")
     }
 }
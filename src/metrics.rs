@@ -0,0 +1,205 @@
+use std::{
+    sync::{Arc, LazyLock},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    extract::MatchedPath,
+    http::{header, HeaderValue, Request},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use prometheus::{
+    core::{Collector, Desc},
+    proto::MetricFamily,
+    Encoder, Gauge, HistogramOpts, HistogramVec, IntCounterVec, Opts, Registry, TextEncoder,
+};
+
+use crate::cache::{CacheStats, NearbyCache};
+
+/// Every metric Thinner exports, registered on a registry of its own.
+pub struct Metrics {
+    registry: Registry,
+    http_requests: IntCounterVec,
+    http_request_duration: HistogramVec,
+    upstream_requests: IntCounterVec,
+    upstream_request_duration: HistogramVec,
+}
+
+pub static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+impl Metrics {
+    fn new() -> Self {
+        let registry = Registry::new_custom(Some("thinner".to_string()), None)
+            .expect("The metrics prefix is valid.");
+
+        let http_requests = IntCounterVec::new(
+            Opts::new("http_requests_total", "HTTP requests by route and status."),
+            &["method", "route", "status"],
+        )
+        .unwrap();
+        let http_request_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "http_request_duration_seconds",
+                "HTTP request latency by route and status.",
+            ),
+            &["method", "route", "status"],
+        )
+        .unwrap();
+        let upstream_requests = IntCounterVec::new(
+            Opts::new(
+                "upstream_requests_total",
+                "StationAPI call attempts by method, upstream and gRPC status code.",
+            ),
+            &["method", "upstream", "code"],
+        )
+        .unwrap();
+        let upstream_request_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "upstream_request_duration_seconds",
+                "StationAPI call attempt latency by method and upstream.",
+            ),
+            &["method", "upstream"],
+        )
+        .unwrap();
+
+        registry.register(Box::new(http_requests.clone())).unwrap();
+        registry
+            .register(Box::new(http_request_duration.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(upstream_requests.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(upstream_request_duration.clone()))
+            .unwrap();
+
+        Self {
+            registry,
+            http_requests,
+            http_request_duration,
+            upstream_requests,
+            upstream_request_duration,
+        }
+    }
+
+    /// Exports the `/nearby` cache counters, read at scrape time.
+    pub fn register_cache(&self, cache: Arc<NearbyCache>) {
+        self.registry
+            .register(Box::new(CacheCollector::new(cache)))
+            .unwrap();
+    }
+
+    /// Records one StationAPI call attempt; `code` is the gRPC status code name.
+    pub fn observe_upstream(&self, method: &str, upstream: &str, code: &str, elapsed: Duration) {
+        self.upstream_requests
+            .with_label_values(&[method, upstream, code])
+            .inc();
+        self.upstream_request_duration
+            .with_label_values(&[method, upstream])
+            .observe(elapsed.as_secs_f64());
+    }
+}
+
+/// Counts every request and its latency, labelled with the matched route
+/// rather than the raw path to keep the label set bounded.
+pub async fn track<B>(request: Request<B>, next: Next<B>) -> Response {
+    let method = request.method().clone();
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map_or("unmatched", |p| p.as_str())
+        .to_string();
+
+    let start = Instant::now();
+    let response = next.run(request).await;
+    let status = response.status();
+
+    let labels = [method.as_str(), route.as_str(), status.as_str()];
+    METRICS.http_requests.with_label_values(&labels).inc();
+    METRICS
+        .http_request_duration
+        .with_label_values(&labels)
+        .observe(start.elapsed().as_secs_f64());
+    response
+}
+
+/// `/metrics` in the Prometheus text exposition format.
+pub async fn render() -> Response {
+    let encoder = TextEncoder::new();
+    let mut body = Vec::new();
+    if let Err(e) = encoder.encode(&METRICS.registry.gather(), &mut body) {
+        tracing::error!("Failed to encode metrics: {}", e);
+    }
+
+    (
+        [(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("text/plain; version=0.0.4"),
+        )],
+        body,
+    )
+        .into_response()
+}
+
+/// Turns [`NearbyCache::stats`] into counters and a hit ratio on every scrape.
+struct CacheCollector {
+    cache: Arc<NearbyCache>,
+    descs: Vec<Desc>,
+}
+
+impl CacheCollector {
+    fn new(cache: Arc<NearbyCache>) -> Self {
+        let (lookups, hit_ratio) = Self::metrics(&CacheStats::default());
+        let descs = lookups
+            .desc()
+            .into_iter()
+            .chain(hit_ratio.desc())
+            .cloned()
+            .collect();
+        Self { cache, descs }
+    }
+
+    fn metrics(stats: &CacheStats) -> (IntCounterVec, Gauge) {
+        let lookups = IntCounterVec::new(
+            Opts::new("cache_lookups_total", "`/nearby` cache lookups by result."),
+            &["result"],
+        )
+        .unwrap();
+        for (result, count) in [
+            ("hit", stats.hits),
+            ("stale", stats.stale_hits),
+            ("stale_if_error", stats.stale_if_error_hits),
+            ("miss", stats.misses),
+        ] {
+            lookups.with_label_values(&[result]).inc_by(count);
+        }
+
+        let hit_ratio = Gauge::new(
+            "cache_hit_ratio",
+            "Share of `/nearby` cache lookups answered from the cache, stale or not.",
+        )
+        .unwrap();
+        let served = stats.hits + stats.stale_hits + stats.stale_if_error_hits;
+        let total = served + stats.misses;
+        if total > 0 {
+            hit_ratio.set(served as f64 / total as f64);
+        }
+        (lookups, hit_ratio)
+    }
+}
+
+impl Collector for CacheCollector {
+    fn desc(&self) -> Vec<&Desc> {
+        self.descs.iter().collect()
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        let (lookups, hit_ratio) = Self::metrics(&self.cache.stats());
+        lookups
+            .collect()
+            .into_iter()
+            .chain(hit_ratio.collect())
+            .collect()
+    }
+}
@@ -0,0 +1,308 @@
+use std::str::FromStr;
+
+const GEOHASH_ALPHABET: &[u8; 32] = b"0123456789bcdefghjkmnpqrstuvwxyz";
+
+/// How user coordinates are coarsened before they leave the handler.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PrivacyMode {
+    Off,
+    /// Round to this many decimal places; 3 is roughly 100 m.
+    Round(u32),
+    /// Snap to the centre of a geohash cell of this many characters; 7 is roughly 150 m.
+    Geohash(usize),
+}
+
+impl FromStr for PrivacyMode {
+    type Err = String;
+
+    /// `off`, `round:<decimals>` or `geohash:<length>`; the precision defaults to 3 and 7.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (mode, precision) = match s.split_once(':') {
+            Some((mode, precision)) => (mode, Some(precision)),
+            None => (s, None),
+        };
+        let invalid = || format!("`{}` is not a valid precision.", s);
+        match mode {
+            "off" if precision.is_none() => Ok(PrivacyMode::Off),
+            "round" => match precision {
+                Some(p) => p
+                    .parse()
+                    .ok()
+                    .filter(|&d| d <= 10)
+                    .map(PrivacyMode::Round)
+                    .ok_or_else(invalid),
+                None => Ok(PrivacyMode::Round(3)),
+            },
+            "geohash" => match precision {
+                Some(p) => p
+                    .parse()
+                    .ok()
+                    .filter(|l| (1..=12).contains(l))
+                    .map(PrivacyMode::Geohash)
+                    .ok_or_else(invalid),
+                None => Ok(PrivacyMode::Geohash(7)),
+            },
+            _ => Err(format!(
+                "`{}` is not one of off, round[:<decimals>] or geohash[:<length>].",
+                s
+            )),
+        }
+    }
+}
+
+/// Where coarsened coordinates are used instead of the precise ones.
+#[derive(Debug, Clone, Copy)]
+pub struct Privacy {
+    pub mode: PrivacyMode,
+    /// Also coarsen the coordinates sent to the data source, at the cost of ranking accuracy.
+    pub upstream: bool,
+}
+
+impl Privacy {
+    /// Coarsened coordinates, used for cache keys and, if enabled, upstream calls.
+    pub fn reduce(&self, latitude: f64, longitude: f64) -> (f64, f64) {
+        match self.mode {
+            PrivacyMode::Off => (latitude, longitude),
+            PrivacyMode::Round(decimals) => {
+                let scale = 10f64.powi(decimals as i32);
+                (
+                    (latitude * scale).round() / scale,
+                    (longitude * scale).round() / scale,
+                )
+            }
+            PrivacyMode::Geohash(length) => geohash_center(&geohash(latitude, longitude, length)),
+        }
+    }
+
+    /// Coordinates to send to the data source.
+    pub fn upstream(&self, latitude: f64, longitude: f64) -> (f64, f64) {
+        if self.upstream {
+            self.reduce(latitude, longitude)
+        } else {
+            (latitude, longitude)
+        }
+    }
+
+    /// Rewrites `latitude` and `longitude` in a query string so it is safe to log.
+    ///
+    /// The query is decoded the same way the `Query` extractor does, so percent-encoded
+    /// keys cannot smuggle coordinates past the redaction.
+    pub fn redact_query(&self, query: &str) -> String {
+        if self.mode == PrivacyMode::Off {
+            return query.to_string();
+        }
+
+        let pairs = form_urlencoded::parse(query.as_bytes()).collect::<Vec<_>>();
+        let value = |name: &str| {
+            pairs
+                .iter()
+                .find(|(key, _)| key == name)
+                .and_then(|(_, value)| value.parse::<f64>().ok())
+        };
+        let replacement = match (value("latitude"), value("longitude")) {
+            (Some(latitude), Some(longitude)) => match self.mode {
+                PrivacyMode::Geohash(length) => {
+                    vec![("geohash", geohash(latitude, longitude, length))]
+                }
+                _ => {
+                    let (latitude, longitude) = self.reduce(latitude, longitude);
+                    vec![
+                        ("latitude", latitude.to_string()),
+                        ("longitude", longitude.to_string()),
+                    ]
+                }
+            },
+            _ => Vec::new(),
+        };
+
+        form_urlencoded::Serializer::new(String::new())
+            .extend_pairs(
+                pairs
+                    .iter()
+                    .filter(|(key, _)| key != "latitude" && key != "longitude"),
+            )
+            .extend_pairs(replacement)
+            .finish()
+    }
+}
+
+fn geohash(latitude: f64, longitude: f64, length: usize) -> String {
+    let (mut lat_range, mut lon_range) = ((-90.0, 90.0), (-180.0, 180.0));
+    let mut hash = String::with_capacity(length);
+    let mut even = true;
+    let (mut bits, mut ch) = (0, 0);
+
+    while hash.len() < length {
+        let (range, value) = if even {
+            (&mut lon_range, longitude)
+        } else {
+            (&mut lat_range, latitude)
+        };
+        let mid = (range.0 + range.1) / 2.0;
+        ch <<= 1;
+        if value >= mid {
+            ch |= 1;
+            range.0 = mid;
+        } else {
+            range.1 = mid;
+        }
+        even = !even;
+
+        bits += 1;
+        if bits == 5 {
+            hash.push(GEOHASH_ALPHABET[ch] as char);
+            bits = 0;
+            ch = 0;
+        }
+    }
+    hash
+}
+
+fn geohash_center(hash: &str) -> (f64, f64) {
+    let (mut lat_range, mut lon_range) = ((-90.0, 90.0), (-180.0, 180.0));
+    let mut even = true;
+
+    for c in hash.bytes() {
+        let index = GEOHASH_ALPHABET
+            .iter()
+            .position(|&a| a == c)
+            .expect("Geohashes are built from the alphabet.");
+        for shift in (0..5).rev() {
+            let range = if even { &mut lon_range } else { &mut lat_range };
+            let mid = (range.0 + range.1) / 2.0;
+            if (index >> shift) & 1 == 1 {
+                range.0 = mid;
+            } else {
+                range.1 = mid;
+            }
+            even = !even;
+        }
+    }
+
+    (
+        (lat_range.0 + lat_range.1) / 2.0,
+        (lon_range.0 + lon_range.1) / 2.0,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOKYO: (f64, f64) = (35.681236, 139.767125);
+
+    fn privacy(mode: PrivacyMode) -> Privacy {
+        Privacy {
+            mode,
+            upstream: false,
+        }
+    }
+
+    #[test]
+    fn geohash_matches_known_vectors() {
+        assert_eq!(geohash(57.64911, 10.40744, 11), "u4pruydqqvj");
+        assert_eq!(geohash(TOKYO.0, TOKYO.1, 7), "xn76urx");
+        assert_eq!(geohash(TOKYO.0, TOKYO.1, 5), "xn76u");
+        assert_eq!(geohash(0.0, 0.0, 1), "s");
+        assert_eq!(geohash(-90.0, -180.0, 4), "0000");
+    }
+
+    #[test]
+    fn geohash_center_lies_in_its_cell() {
+        let (latitude, longitude) = geohash_center("xn76urx");
+        assert_eq!(geohash(latitude, longitude, 7), "xn76urx");
+        // A 7-character cell is about 153 m by 153 m.
+        assert!((latitude - TOKYO.0).abs() < 0.0007);
+        assert!((longitude - TOKYO.1).abs() < 0.0007);
+    }
+
+    #[test]
+    fn reduce_snaps_nearby_points_together() {
+        let geohash = privacy(PrivacyMode::Geohash(7));
+        assert_eq!(
+            geohash.reduce(TOKYO.0, TOKYO.1),
+            geohash.reduce(35.6812, 139.7672)
+        );
+        assert_eq!(
+            privacy(PrivacyMode::Round(3)).reduce(TOKYO.0, TOKYO.1),
+            (35.681, 139.767)
+        );
+        assert_eq!(privacy(PrivacyMode::Off).reduce(TOKYO.0, TOKYO.1), TOKYO);
+    }
+
+    #[test]
+    fn upstream_coordinates_are_only_reduced_when_enabled() {
+        let mut privacy = privacy(PrivacyMode::Round(3));
+        assert_eq!(privacy.upstream(TOKYO.0, TOKYO.1), TOKYO);
+        privacy.upstream = true;
+        assert_eq!(privacy.upstream(TOKYO.0, TOKYO.1), (35.681, 139.767));
+    }
+
+    #[test]
+    fn redact_query_never_logs_raw_coordinates() {
+        let queries = [
+            "latitude=35.681236&longitude=139.767125&limit=5",
+            "limit=5&longitude=139.767125&latitude=35.681236",
+            "latitude=35.681236&latitude=35.681236&longitude=139.767125",
+            // Only one coordinate, or one that does not parse.
+            "latitude=35.681236&limit=5",
+            "longitude=139.767125",
+            "latitude=35.681236&longitude=east",
+            "latitude=35.681236&longitude",
+            // Percent-encoded keys and values, which the extractor decodes.
+            "%6Catitude=35.681236&longitude=139.767125",
+            "latitude=35%2E681236&%6C%6Fngitude=139%2e767125",
+        ];
+        for mode in [PrivacyMode::Round(3), PrivacyMode::Geohash(7)] {
+            for query in queries {
+                let redacted = privacy(mode).redact_query(query);
+                assert!(
+                    !["35.681236", "35%2E681236", "139.767125", "139%2e767125"]
+                        .iter()
+                        .any(|raw| redacted.contains(raw)),
+                    "{:?} left raw coordinates in {:?}",
+                    mode,
+                    redacted
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn redact_query_keeps_other_parameters() {
+        let query = "latitude=35.681236&longitude=139.767125&limit=5&lang=en";
+        assert_eq!(
+            privacy(PrivacyMode::Geohash(7)).redact_query(query),
+            "limit=5&lang=en&geohash=xn76urx"
+        );
+        assert_eq!(
+            privacy(PrivacyMode::Round(3)).redact_query(query),
+            "limit=5&lang=en&latitude=35.681&longitude=139.767"
+        );
+        assert_eq!(privacy(PrivacyMode::Off).redact_query(query), query);
+        assert_eq!(
+            privacy(PrivacyMode::Geohash(7))
+                .redact_query("%6Catitude=35.681236&longitude=139.767125"),
+            "geohash=xn76urx"
+        );
+    }
+
+    #[test]
+    fn parses_modes() {
+        assert_eq!("off".parse(), Ok(PrivacyMode::Off));
+        assert_eq!("round".parse(), Ok(PrivacyMode::Round(3)));
+        assert_eq!("round:4".parse(), Ok(PrivacyMode::Round(4)));
+        assert_eq!("geohash".parse(), Ok(PrivacyMode::Geohash(7)));
+        assert_eq!("geohash:6".parse(), Ok(PrivacyMode::Geohash(6)));
+        for invalid in [
+            "off:1",
+            "round:11",
+            "round:x",
+            "geohash:0",
+            "geohash:13",
+            "exact",
+        ] {
+            assert!(invalid.parse::<PrivacyMode>().is_err(), "{}", invalid);
+        }
+    }
+}
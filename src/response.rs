@@ -0,0 +1,328 @@
+use axum::{
+    http::{header, HeaderMap},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+
+use crate::{
+    geo::haversine_distance,
+    lang::{company_name, line_name, station_name, Language},
+    station_api::{Company, Line, LineSymbol, Station, StationNumber},
+};
+
+/// Output representation requested by the client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Text,
+    Json,
+}
+
+impl Format {
+    /// `?format=` wins over the `Accept` header; plain text is the default.
+    pub fn negotiate(format: Option<&str>, headers: &HeaderMap) -> Self {
+        match format {
+            Some(f) if f.eq_ignore_ascii_case("json") => return Format::Json,
+            Some(f) if f.eq_ignore_ascii_case("text") => return Format::Text,
+            _ => {}
+        }
+
+        let accepts_json = headers
+            .get_all(header::ACCEPT)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .any(|v| v.trim().starts_with("application/json"));
+
+        if accepts_json {
+            Format::Json
+        } else {
+            Format::Text
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ThinStation {
+    pub id: u32,
+    pub group_id: u32,
+    pub name: String,
+    pub name_katakana: String,
+    pub name_roman: Option<String>,
+    pub name_chinese: Option<String>,
+    pub name_korean: Option<String>,
+    pub latitude: f64,
+    pub longitude: f64,
+    /// Distance in metres from the queried coordinates, for coordinate lookups.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub distance: Option<f64>,
+    pub station_numbers: Vec<ThinStationNumber>,
+    pub lines: Vec<ThinLine>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ThinStationNumber {
+    pub station_number: String,
+    pub line_symbol: String,
+    pub line_symbol_color: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ThinLine {
+    pub id: u32,
+    pub name_short: String,
+    pub name_full: String,
+    pub name_roman: Option<String>,
+    pub name_chinese: Option<String>,
+    pub name_korean: Option<String>,
+    pub color: String,
+}
+
+/// Line as a resource of its own, with everything needed to render a line badge.
+#[derive(Debug, Serialize)]
+pub struct ThinLineDetail {
+    pub id: u32,
+    pub name_short: String,
+    pub name_katakana: String,
+    pub name_full: String,
+    pub name_roman: Option<String>,
+    pub name_chinese: Option<String>,
+    pub name_korean: Option<String>,
+    pub color: String,
+    pub line_type: &'static str,
+    pub company: Option<ThinCompany>,
+    pub line_symbols: Vec<ThinLineSymbol>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ThinCompany {
+    pub id: u32,
+    pub name_short: String,
+    pub name_full: String,
+    pub name_english_short: String,
+    pub name_english_full: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ThinLineSymbol {
+    pub symbol: String,
+    pub color: String,
+    pub shape: String,
+}
+
+impl ThinStation {
+    pub fn new(station: &Station, origin: Option<(f64, f64)>) -> Self {
+        Self {
+            id: station.id,
+            group_id: station.group_id,
+            name: station.name.clone(),
+            name_katakana: station.name_katakana.clone(),
+            name_roman: station.name_roman.clone(),
+            name_chinese: station.name_chinese.clone(),
+            name_korean: station.name_korean.clone(),
+            latitude: station.latitude,
+            longitude: station.longitude,
+            distance: origin.map(|(latitude, longitude)| {
+                haversine_distance(latitude, longitude, station.latitude, station.longitude)
+            }),
+            station_numbers: station
+                .station_numbers
+                .iter()
+                .map(ThinStationNumber::from)
+                .collect(),
+            lines: station.lines.iter().map(ThinLine::from).collect(),
+        }
+    }
+}
+
+impl From<&StationNumber> for ThinStationNumber {
+    fn from(number: &StationNumber) -> Self {
+        Self {
+            station_number: number.station_number.clone(),
+            line_symbol: number.line_symbol.clone(),
+            line_symbol_color: number.line_symbol_color.clone(),
+        }
+    }
+}
+
+impl From<&Line> for ThinLine {
+    fn from(line: &Line) -> Self {
+        Self {
+            id: line.id,
+            name_short: line.name_short.clone(),
+            name_full: line.name_full.clone(),
+            name_roman: line.name_roman.clone(),
+            name_chinese: line.name_chinese.clone(),
+            name_korean: line.name_korean.clone(),
+            color: line.color.clone(),
+        }
+    }
+}
+
+impl From<&Line> for ThinLineDetail {
+    fn from(line: &Line) -> Self {
+        Self {
+            id: line.id,
+            name_short: line.name_short.clone(),
+            name_katakana: line.name_katakana.clone(),
+            name_full: line.name_full.clone(),
+            name_roman: line.name_roman.clone(),
+            name_chinese: line.name_chinese.clone(),
+            name_korean: line.name_korean.clone(),
+            color: line.color.clone(),
+            line_type: line.line_type().as_str_name(),
+            company: line.company.as_ref().map(ThinCompany::from),
+            line_symbols: line.line_symbols.iter().map(ThinLineSymbol::from).collect(),
+        }
+    }
+}
+
+impl From<&Company> for ThinCompany {
+    fn from(company: &Company) -> Self {
+        Self {
+            id: company.id,
+            name_short: company.name_short.clone(),
+            name_full: company.name_full.clone(),
+            name_english_short: company.name_english_short.clone(),
+            name_english_full: company.name_english_full.clone(),
+        }
+    }
+}
+
+impl From<&LineSymbol> for ThinLineSymbol {
+    fn from(symbol: &LineSymbol) -> Self {
+        Self {
+            symbol: symbol.symbol.clone(),
+            color: symbol.color.clone(),
+            shape: symbol.shape.clone(),
+        }
+    }
+}
+
+/// Two-line plain-text form: the station name, then the comma-joined line names.
+pub fn to_text(station: &Station, lang: Language) -> String {
+    let lines = station
+        .lines
+        .iter()
+        .map(|l| line_name(l, lang))
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    format!("{}\n{}", station_name(station, lang), lines)
+}
+
+/// Text form of a station plus, when `origin` is given, a third line with its
+/// distance from there in whole metres.
+pub fn to_text_with_distance(
+    station: &Station,
+    origin: Option<(f64, f64)>,
+    lang: Language,
+) -> String {
+    let text = to_text(station, lang);
+    match origin {
+        Some((latitude, longitude)) => {
+            let distance =
+                haversine_distance(latitude, longitude, station.latitude, station.longitude);
+            format!("{}\n{:.0} m", text, distance)
+        }
+        None => text,
+    }
+}
+
+/// Two-line plain-text form for a line: the line name, then its operator.
+pub fn to_line_text(line: &Line, lang: Language) -> String {
+    format!(
+        "{}\n{}",
+        line_name(line, lang),
+        line.company.as_ref().map_or("", |c| company_name(c, lang))
+    )
+}
+
+/// One-line plain-text form for a stop sequence: the station numbers, then the name.
+pub fn to_stop_text(station: &Station, lang: Language) -> String {
+    station
+        .station_numbers
+        .iter()
+        .map(|n| n.station_number.as_str())
+        .chain(std::iter::once(station_name(station, lang)))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Renders a single station; `origin` is the queried point, if any, used for the distance.
+pub fn render_station(
+    format: Format,
+    station: &Station,
+    origin: Option<(f64, f64)>,
+    lang: Language,
+) -> Response {
+    match format {
+        Format::Json => Json(ThinStation::new(station, origin)).into_response(),
+        Format::Text => to_text(station, lang).into_response(),
+    }
+}
+
+/// Renders a list of stations; the text form separates stations with a blank line.
+pub fn render_stations(
+    format: Format,
+    stations: &[Station],
+    origin: Option<(f64, f64)>,
+    lang: Language,
+) -> Response {
+    match format {
+        Format::Json => Json(
+            stations
+                .iter()
+                .map(|s| ThinStation::new(s, origin))
+                .collect::<Vec<_>>(),
+        )
+        .into_response(),
+        Format::Text => stations
+            .iter()
+            .map(|s| to_text_with_distance(s, origin, lang))
+            .collect::<Vec<_>>()
+            .join("\n\n")
+            .into_response(),
+    }
+}
+
+/// Renders the ordered stops of a line; the text form has one stop per line.
+pub fn render_line_stations(format: Format, stations: &[Station], lang: Language) -> Response {
+    match format {
+        Format::Json => Json(
+            stations
+                .iter()
+                .map(|s| ThinStation::new(s, None))
+                .collect::<Vec<_>>(),
+        )
+        .into_response(),
+        Format::Text => stations
+            .iter()
+            .map(|s| to_stop_text(s, lang))
+            .collect::<Vec<_>>()
+            .join("\n")
+            .into_response(),
+    }
+}
+
+pub fn render_line(format: Format, line: &Line, lang: Language) -> Response {
+    match format {
+        Format::Json => Json(ThinLineDetail::from(line)).into_response(),
+        Format::Text => to_line_text(line, lang).into_response(),
+    }
+}
+
+/// Renders a list of lines; the text form separates lines with a blank line.
+pub fn render_lines(format: Format, lines: &[Line], lang: Language) -> Response {
+    match format {
+        Format::Json => {
+            Json(lines.iter().map(ThinLineDetail::from).collect::<Vec<_>>()).into_response()
+        }
+        Format::Text => lines
+            .iter()
+            .map(|l| to_line_text(l, lang))
+            .collect::<Vec<_>>()
+            .join("\n\n")
+            .into_response(),
+    }
+}
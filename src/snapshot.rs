@@ -0,0 +1,102 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use prost::Message;
+
+use crate::{
+    breaker::BoxError,
+    dataset::{Snapshot, SnapshotLine, SNAPSHOT_VERSION},
+    error::AppError,
+    station_api::Station,
+    upstream::{self, Client, PROBE_COORDINATES},
+};
+
+/// Walks StationAPI line by line and collects everything reachable.
+///
+/// StationAPI has no way to list every line, so the walk starts from the lines
+/// serving the station nearest to central Tokyo plus any `seed_line_ids`, and
+/// follows every line that a visited station is on.
+pub async fn take(client: &Client, seed_line_ids: &[u32]) -> Result<Snapshot, BoxError> {
+    let (latitude, longitude) = PROBE_COORDINATES;
+    let origin = upstream::fetch_nearby(client, latitude, longitude, 1).await?;
+
+    let mut queue = origin
+        .iter()
+        .flat_map(|s| s.lines.iter().map(|l| l.id))
+        .chain(seed_line_ids.iter().copied())
+        .collect::<VecDeque<_>>();
+    let mut seen = queue.iter().copied().collect::<HashSet<_>>();
+    let mut stations: Vec<Station> = Vec::new();
+    let mut station_index = HashMap::new();
+    let mut lines = Vec::new();
+
+    while let Some(line_id) = queue.pop_front() {
+        let line = match upstream::fetch_line(client, line_id).await {
+            Ok(line) => line,
+            Err(AppError::LineNotFound) => {
+                tracing::warn!("Skipping line {}, which StationAPI does not know", line_id);
+                continue;
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let stops = match upstream::fetch_line_stations(client, line_id).await {
+            Ok(stops) => stops,
+            // An empty or unknown line must not abort the whole walk; keep it without stops.
+            Err(e @ (AppError::LineNotFound | AppError::StationNotFound)) => {
+                tracing::warn!("Line {} has no stops: {}", line_id, e);
+                Vec::new()
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        let station_ids = stops.iter().map(|s| s.id).collect();
+        for station in stops {
+            for line in &station.lines {
+                if seen.insert(line.id) {
+                    queue.push_back(line.id);
+                }
+            }
+            station_index.entry(station.id).or_insert_with(|| {
+                stations.push(station);
+                stations.len() - 1
+            });
+        }
+        lines.push(SnapshotLine {
+            line: Some(line),
+            station_ids,
+        });
+        tracing::info!(
+            "Fetched {} lines and {} stations, {} lines to go",
+            lines.len(),
+            stations.len(),
+            queue.len()
+        );
+    }
+
+    Ok(Snapshot {
+        version: SNAPSHOT_VERSION,
+        created_at: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+        stations,
+        lines,
+    })
+}
+
+/// Writes a snapshot to `path`, replacing any previous one.
+pub fn write(snapshot: &Snapshot, path: impl AsRef<Path>) -> Result<(), BoxError> {
+    // Write next to the target first so a failed export never leaves a truncated file behind.
+    let path = path.as_ref();
+    let partial = path.with_extension("partial");
+    std::fs::write(&partial, snapshot.encode_to_vec())?;
+    std::fs::rename(&partial, path)?;
+
+    tracing::info!(
+        "Wrote {} lines and {} stations to {}",
+        snapshot.lines.len(),
+        snapshot.stations.len(),
+        path.display()
+    );
+    Ok(())
+}
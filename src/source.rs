@@ -0,0 +1,334 @@
+use std::{
+    future::Future,
+    path::PathBuf,
+    str::FromStr,
+    sync::{Arc, RwLock},
+    time::{Duration, SystemTime},
+};
+
+use crate::{
+    breaker::BoxError,
+    dataset::{Dataset, DatasetStatus},
+    error::AppError,
+    snapshot,
+    station_api::{Line, Station},
+    upstream::{self, Client, ReadyProbe, UpstreamStatus},
+};
+
+/// Which kind of [`Source`] to serve from, as set by `DATA_SOURCE`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SourceKind {
+    /// Every lookup goes to StationAPI.
+    Remote,
+    /// Every lookup is answered from the dataset at `DATASET_PATH`, with no network access.
+    Local,
+    /// Lookups are answered from the dataset, falling back to StationAPI for misses
+    /// and while the dataset is older than `DATASET_MAX_AGE_SECS`.
+    Hybrid,
+}
+
+impl FromStr for SourceKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "remote" => Ok(SourceKind::Remote),
+            "local" => Ok(SourceKind::Local),
+            "hybrid" => Ok(SourceKind::Hybrid),
+            _ => Err(format!("`{}` is not one of remote, local or hybrid.", s)),
+        }
+    }
+}
+
+/// Where station data comes from: StationAPI, a snapshot loaded into memory, or both.
+#[derive(Clone)]
+pub enum Source {
+    Remote(Client),
+    Local(Arc<Dataset>),
+    Hybrid(Arc<Hybrid>),
+}
+
+/// Local dataset backed by StationAPI, refreshed in the background.
+pub struct Hybrid {
+    client: Client,
+    dataset: RwLock<Arc<Dataset>>,
+    /// Age after which local answers are only used if StationAPI fails.
+    max_age: Duration,
+    /// Where refreshed snapshots are written, so a restart starts from the latest one.
+    path: Option<PathBuf>,
+}
+
+impl Hybrid {
+    pub fn new(
+        client: Client,
+        dataset: Dataset,
+        max_age: Duration,
+        path: Option<PathBuf>,
+    ) -> Arc<Self> {
+        Arc::new(Self {
+            client,
+            dataset: RwLock::new(Arc::new(dataset)),
+            max_age,
+            path,
+        })
+    }
+
+    fn dataset(&self) -> Arc<Dataset> {
+        self.dataset.read().unwrap().clone()
+    }
+
+    fn is_fresh(&self, dataset: &Dataset) -> bool {
+        SystemTime::now()
+            .duration_since(dataset.created_at())
+            .is_ok_and(|age| age <= self.max_age)
+    }
+
+    /// Re-exports StationAPI every `interval`, starting right away if the dataset is stale.
+    pub fn spawn_refresh(self: &Arc<Self>, interval: Duration) {
+        let hybrid = self.clone();
+        tokio::spawn(async move {
+            if hybrid.is_fresh(&hybrid.dataset()) {
+                tokio::time::sleep(interval).await;
+            }
+            loop {
+                if let Err(e) = hybrid.refresh().await {
+                    tracing::warn!("Failed to refresh the local dataset: {}", e);
+                }
+                tokio::time::sleep(interval).await;
+            }
+        });
+    }
+
+    async fn refresh(&self) -> Result<(), BoxError> {
+        // Seed the walk with every known line so islands that aren't connected to
+        // the rest of the network stay in the dataset.
+        let seed_line_ids = self.dataset().line_ids();
+        let mut snapshot = snapshot::take(&self.client, &seed_line_ids).await?;
+        if let Some(path) = self.path.clone() {
+            snapshot = tokio::task::spawn_blocking(move || {
+                snapshot::write(&snapshot, path).map(|()| snapshot)
+            })
+            .await??;
+        }
+
+        let dataset = Dataset::from_snapshot(snapshot);
+        let status = dataset.status();
+        *self.dataset.write().unwrap() = Arc::new(dataset);
+        tracing::info!(
+            "Refreshed the local dataset: {} stations and {} lines",
+            status.stations,
+            status.lines
+        );
+        Ok(())
+    }
+
+    /// Answers from the dataset when it is fresh and has the answer, and from
+    /// StationAPI otherwise; a stale local answer still beats a failing upstream.
+    ///
+    /// `local` returns `None` when the dataset does not know, and an error when it
+    /// knows the answer is one.
+    async fn local_first<T, Fut>(
+        &self,
+        local: impl FnOnce(&Dataset) -> Option<Result<T, AppError>>,
+        remote: impl FnOnce(Client) -> Fut,
+    ) -> Result<T, AppError>
+    where
+        Fut: Future<Output = Result<T, AppError>>,
+    {
+        let dataset = self.dataset();
+        let local = match local(&dataset) {
+            Some(value) if self.is_fresh(&dataset) => return value,
+            local => local,
+        };
+
+        match remote(self.client.clone()).await {
+            Ok(value) => Ok(value),
+            Err(
+                e @ (AppError::Upstream(_) | AppError::UpstreamTimeout | AppError::CircuitOpen(_)),
+            ) => {
+                let Some(value) = local else {
+                    return Err(e);
+                };
+                tracing::warn!("Serving from a stale local dataset: {}", e);
+                value
+            }
+            Err(e) => Err(e),
+        }
+    }
+}
+
+impl Source {
+    /// Whether answers come straight from memory, in which case caching them is pointless.
+    pub fn serves_locally(&self) -> bool {
+        match self {
+            Source::Remote(_) => false,
+            Source::Local(_) => true,
+            Source::Hybrid(hybrid) => hybrid.is_fresh(&hybrid.dataset()),
+        }
+    }
+
+    /// Ready once a dataset is loaded or StationAPI answers, see [`Client::is_ready`].
+    pub async fn is_ready(&self, probe: ReadyProbe) -> bool {
+        match self {
+            Source::Remote(client) => client.is_ready(probe).await,
+            // A local dataset that failed to load never gets this far.
+            Source::Local(_) => true,
+            Source::Hybrid(hybrid) => {
+                !hybrid.dataset().is_empty() || hybrid.client.is_ready(probe).await
+            }
+        }
+    }
+
+    pub fn upstream_status(&self) -> Option<Vec<UpstreamStatus>> {
+        match self {
+            Source::Remote(client) => Some(client.status()),
+            Source::Local(_) => None,
+            Source::Hybrid(hybrid) => Some(hybrid.client.status()),
+        }
+    }
+
+    pub fn dataset_status(&self) -> Option<DatasetStatus> {
+        match self {
+            Source::Remote(_) => None,
+            Source::Local(dataset) => Some(dataset.status()),
+            Source::Hybrid(hybrid) => Some(hybrid.dataset().status()),
+        }
+    }
+
+    /// Returns up to `limit` stations around the given point, nearest first.
+    ///
+    /// Local lookups also stop at `radius` metres; StationAPI has no such bound,
+    /// so callers still filter its answers themselves.
+    pub async fn fetch_nearby(
+        &self,
+        latitude: f64,
+        longitude: f64,
+        limit: u32,
+        radius: Option<f64>,
+    ) -> Result<Vec<Station>, AppError> {
+        match self {
+            Source::Remote(client) => {
+                upstream::fetch_nearby(client, latitude, longitude, limit).await
+            }
+            Source::Local(dataset) => dataset.nearby(latitude, longitude, limit, radius),
+            Source::Hybrid(hybrid) => hybrid
+                .local_first(
+                    // Nothing within the radius of a non-empty dataset is an answer too.
+                    |d| match d.nearby(latitude, longitude, limit, radius) {
+                        Err(AppError::StationNotFound) => None,
+                        result => Some(result),
+                    },
+                    |c| async move { upstream::fetch_nearby(&c, latitude, longitude, limit).await },
+                )
+                .await,
+        }
+    }
+
+    pub async fn fetch_station(&self, id: u32) -> Result<Station, AppError> {
+        match self {
+            Source::Remote(client) => upstream::fetch_station(client, id).await,
+            Source::Local(dataset) => dataset.station(id),
+            Source::Hybrid(hybrid) => {
+                hybrid
+                    .local_first(
+                        |d| d.station(id).ok().map(Ok),
+                        |c| async move { upstream::fetch_station(&c, id).await },
+                    )
+                    .await
+            }
+        }
+    }
+
+    pub async fn fetch_stations(&self, ids: Vec<u32>) -> Result<Vec<Station>, AppError> {
+        match self {
+            Source::Remote(client) => upstream::fetch_stations(client, ids).await,
+            Source::Local(dataset) => dataset.stations(&ids),
+            Source::Hybrid(hybrid) => {
+                // Any id missing locally sends the whole lookup upstream.
+                let remote_ids = ids.clone();
+                hybrid
+                    .local_first(
+                        |d| {
+                            ids.iter()
+                                .all(|id| d.contains(*id))
+                                .then(|| d.stations(&ids).ok().map(Ok))
+                                .flatten()
+                        },
+                        |c| async move { upstream::fetch_stations(&c, remote_ids).await },
+                    )
+                    .await
+            }
+        }
+    }
+
+    pub async fn search_stations(
+        &self,
+        station_name: String,
+        limit: u32,
+    ) -> Result<Vec<Station>, AppError> {
+        match self {
+            Source::Remote(client) => upstream::search_stations(client, station_name, limit).await,
+            Source::Local(dataset) => Ok(dataset.search_stations(&station_name, limit)),
+            Source::Hybrid(hybrid) => {
+                let query = station_name.clone();
+                hybrid
+                    .local_first(
+                        |d| non_empty(d.search_stations(&query, limit)).map(Ok),
+                        |c| async move { upstream::search_stations(&c, station_name, limit).await },
+                    )
+                    .await
+            }
+        }
+    }
+
+    pub async fn fetch_line_stations(&self, line_id: u32) -> Result<Vec<Station>, AppError> {
+        match self {
+            Source::Remote(client) => upstream::fetch_line_stations(client, line_id).await,
+            Source::Local(dataset) => dataset.line_stations(line_id),
+            Source::Hybrid(hybrid) => {
+                hybrid
+                    .local_first(
+                        |d| d.line_stations(line_id).ok().map(Ok),
+                        |c| async move { upstream::fetch_line_stations(&c, line_id).await },
+                    )
+                    .await
+            }
+        }
+    }
+
+    pub async fn fetch_line(&self, line_id: u32) -> Result<Line, AppError> {
+        match self {
+            Source::Remote(client) => upstream::fetch_line(client, line_id).await,
+            Source::Local(dataset) => dataset.line(line_id),
+            Source::Hybrid(hybrid) => {
+                hybrid
+                    .local_first(
+                        |d| d.line(line_id).ok().map(Ok),
+                        |c| async move { upstream::fetch_line(&c, line_id).await },
+                    )
+                    .await
+            }
+        }
+    }
+
+    pub async fn search_lines(&self, line_name: String, limit: u32) -> Result<Vec<Line>, AppError> {
+        match self {
+            Source::Remote(client) => upstream::search_lines(client, line_name, limit).await,
+            Source::Local(dataset) => Ok(dataset.search_lines(&line_name, limit)),
+            Source::Hybrid(hybrid) => {
+                let query = line_name.clone();
+                hybrid
+                    .local_first(
+                        |d| non_empty(d.search_lines(&query, limit)).map(Ok),
+                        |c| async move { upstream::search_lines(&c, line_name, limit).await },
+                    )
+                    .await
+            }
+        }
+    }
+}
+
+/// An empty local search result counts as a miss, since StationAPI may know more.
+fn non_empty<T>(items: Vec<T>) -> Option<Vec<T>> {
+    (!items.is_empty()).then_some(items)
+}
@@ -0,0 +1,280 @@
+use std::{cmp::Ordering, collections::BinaryHeap};
+
+use crate::geo::EARTH_RADIUS_M;
+
+/// Static k-d tree over points on the unit sphere.
+///
+/// Points are stored as 3D unit vectors, where the straight-line (chord) distance
+/// grows monotonically with the great-circle distance. That keeps the tree a plain
+/// Euclidean one while still ranking neighbours correctly across the antimeridian
+/// and near the poles.
+pub struct SpatialIndex {
+    /// Balanced tree laid out in place: the median of every slice is its root.
+    nodes: Vec<Node>,
+}
+
+struct Node {
+    point: [f64; 3],
+    item: usize,
+}
+
+/// Candidate in the k-nearest search, ordered by squared chord length.
+struct Candidate {
+    chord2: f64,
+    item: usize,
+}
+
+impl PartialEq for Candidate {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Candidate {}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.chord2
+            .total_cmp(&other.chord2)
+            .then(self.item.cmp(&other.item))
+    }
+}
+
+impl SpatialIndex {
+    /// Builds the index from `(latitude, longitude)` pairs; results refer to their positions.
+    pub fn new(points: impl IntoIterator<Item = (f64, f64)>) -> Self {
+        let mut nodes = points
+            .into_iter()
+            .enumerate()
+            .map(|(item, (latitude, longitude))| Node {
+                point: to_unit_vector(latitude, longitude),
+                item,
+            })
+            .collect::<Vec<_>>();
+        build(&mut nodes, 0);
+        Self { nodes }
+    }
+
+    /// Returns up to `k` items within `max_distance` metres of the point, nearest first.
+    ///
+    /// Pass `usize::MAX` as `k` for a pure radius query, or `f64::INFINITY` as
+    /// `max_distance` for a pure k-nearest one.
+    pub fn nearest(
+        &self,
+        latitude: f64,
+        longitude: f64,
+        k: usize,
+        max_distance: f64,
+    ) -> Vec<usize> {
+        if k == 0 {
+            return Vec::new();
+        }
+
+        let target = to_unit_vector(latitude, longitude);
+        let mut search = Search {
+            target,
+            k,
+            max_chord2: chord2_for_distance(max_distance),
+            best: BinaryHeap::new(),
+        };
+        search.visit(&self.nodes, 0);
+
+        search
+            .best
+            .into_sorted_vec()
+            .into_iter()
+            .map(|c| c.item)
+            .collect()
+    }
+}
+
+struct Search {
+    target: [f64; 3],
+    k: usize,
+    max_chord2: f64,
+    /// Max-heap of the best candidates so far, so the worst one is on top.
+    best: BinaryHeap<Candidate>,
+}
+
+impl Search {
+    fn bound(&self) -> f64 {
+        if self.best.len() < self.k {
+            self.max_chord2
+        } else {
+            self.best.peek().map_or(self.max_chord2, |c| c.chord2)
+        }
+    }
+
+    fn visit(&mut self, nodes: &[Node], depth: usize) {
+        if nodes.is_empty() {
+            return;
+        }
+
+        let mid = nodes.len() / 2;
+        let node = &nodes[mid];
+        let chord2 = squared_distance(&node.point, &self.target);
+        if chord2 <= self.bound() {
+            self.best.push(Candidate {
+                chord2,
+                item: node.item,
+            });
+            if self.best.len() > self.k {
+                self.best.pop();
+            }
+        }
+
+        let axis = depth % 3;
+        let delta = self.target[axis] - node.point[axis];
+        let (near, far) = if delta < 0.0 {
+            (&nodes[..mid], &nodes[mid + 1..])
+        } else {
+            (&nodes[mid + 1..], &nodes[..mid])
+        };
+        self.visit(near, depth + 1);
+        if delta * delta <= self.bound() {
+            self.visit(far, depth + 1);
+        }
+    }
+}
+
+fn build(nodes: &mut [Node], depth: usize) {
+    if nodes.len() <= 1 {
+        return;
+    }
+
+    let axis = depth % 3;
+    let mid = nodes.len() / 2;
+    nodes.select_nth_unstable_by(mid, |a, b| a.point[axis].total_cmp(&b.point[axis]));
+    let (left, right) = nodes.split_at_mut(mid);
+    build(left, depth + 1);
+    build(&mut right[1..], depth + 1);
+}
+
+fn to_unit_vector(latitude: f64, longitude: f64) -> [f64; 3] {
+    let (phi, lambda) = (latitude.to_radians(), longitude.to_radians());
+    [
+        phi.cos() * lambda.cos(),
+        phi.cos() * lambda.sin(),
+        phi.sin(),
+    ]
+}
+
+fn squared_distance(a: &[f64; 3], b: &[f64; 3]) -> f64 {
+    a.iter().zip(b).map(|(x, y)| (x - y).powi(2)).sum()
+}
+
+/// Squared chord length on the unit sphere for a great-circle distance in metres.
+fn chord2_for_distance(distance: f64) -> f64 {
+    let angle = distance / EARTH_RADIUS_M;
+    if angle >= std::f64::consts::PI {
+        // Anything on the sphere is closer than the antipode, which has a chord of 2.
+        return f64::INFINITY;
+    }
+    (2.0 * (angle / 2.0).sin()).powi(2)
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::*;
+    use crate::geo::haversine_distance;
+
+    fn random_points(rng: &mut StdRng, n: usize) -> Vec<(f64, f64)> {
+        (0..n)
+            .map(|_| (rng.gen_range(-90.0..=90.0), rng.gen_range(-180.0..=180.0)))
+            .collect()
+    }
+
+    /// Every item with its distance from the point, nearest first.
+    fn brute_force(points: &[(f64, f64)], latitude: f64, longitude: f64) -> Vec<(f64, usize)> {
+        let mut ranked = points
+            .iter()
+            .enumerate()
+            .map(|(i, &(lat, lon))| (haversine_distance(latitude, longitude, lat, lon), i))
+            .collect::<Vec<_>>();
+        ranked.sort_by(|a, b| a.0.total_cmp(&b.0));
+        ranked
+    }
+
+    #[test]
+    fn k_nearest_matches_brute_force() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let points = random_points(&mut rng, 2_000);
+        let index = SpatialIndex::new(points.iter().copied());
+
+        for _ in 0..200 {
+            let (lat, lon) = random_points(&mut rng, 1)[0];
+            let k = rng.gen_range(1..=30);
+            let expected = brute_force(&points, lat, lon)
+                .into_iter()
+                .take(k)
+                .map(|(_, i)| i)
+                .collect::<Vec<_>>();
+            assert_eq!(index.nearest(lat, lon, k, f64::INFINITY), expected);
+        }
+    }
+
+    #[test]
+    fn radius_only_matches_brute_force() {
+        let mut rng = StdRng::seed_from_u64(2);
+        let points = random_points(&mut rng, 2_000);
+        let index = SpatialIndex::new(points.iter().copied());
+
+        for _ in 0..200 {
+            let (lat, lon) = random_points(&mut rng, 1)[0];
+            let radius = rng.gen_range(1_000.0..3_000_000.0);
+            let found = index.nearest(lat, lon, usize::MAX, radius);
+            let expected = brute_force(&points, lat, lon);
+
+            // Allow for rounding right at the boundary, but nothing else.
+            let inside = expected.iter().filter(|(d, _)| *d <= radius - 1e-3).count();
+            let maybe = expected.iter().filter(|(d, _)| *d <= radius + 1e-3).count();
+            assert!((inside..=maybe).contains(&found.len()));
+            let prefix = expected
+                .iter()
+                .take(found.len())
+                .map(|&(_, i)| i)
+                .collect::<Vec<_>>();
+            assert_eq!(found, prefix);
+        }
+    }
+
+    #[test]
+    fn k_and_radius_together() {
+        let points = [
+            (35.0, 139.0),
+            (35.001, 139.0),
+            (35.002, 139.0),
+            (36.0, 139.0),
+        ];
+        let index = SpatialIndex::new(points);
+
+        assert_eq!(index.nearest(35.0, 139.0, 2, 1_000.0), vec![0, 1]);
+        assert_eq!(index.nearest(35.0, 139.0, 10, 1_000.0), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn finds_neighbours_across_the_antimeridian() {
+        let points = [(0.0, 179.99), (0.0, -179.99), (0.0, 178.0), (0.0, -178.0)];
+        let index = SpatialIndex::new(points);
+
+        assert_eq!(index.nearest(0.0, -179.995, 2, f64::INFINITY), vec![1, 0]);
+        assert_eq!(index.nearest(0.0, 179.995, 1, f64::INFINITY), vec![0]);
+        assert_eq!(index.nearest(0.0, 180.0, usize::MAX, 5_000.0), vec![0, 1]);
+    }
+
+    #[test]
+    fn empty_index_finds_nothing() {
+        let index = SpatialIndex::new(std::iter::empty());
+
+        assert!(index.nearest(35.0, 139.0, 5, f64::INFINITY).is_empty());
+        assert!(index.nearest(35.0, 139.0, usize::MAX, 1_000.0).is_empty());
+    }
+}
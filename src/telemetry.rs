@@ -0,0 +1,146 @@
+use std::env;
+
+use axum::{
+    extract::MatchedPath,
+    http::{HeaderMap, Request},
+    middleware::Next,
+    response::Response,
+};
+use opentelemetry::{
+    global,
+    propagation::{Extractor, Injector},
+    trace::TracerProvider as _,
+    KeyValue,
+};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::{propagation::TraceContextPropagator, runtime, trace, Resource};
+use tonic::metadata::{MetadataKey, MetadataMap, MetadataValue};
+use tracing::{field, Instrument, Span};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::{
+    filter::LevelFilter,
+    layer::{Layer, SubscriberExt},
+    util::SubscriberInitExt,
+};
+
+/// Sets up logging in `LOG_FORMAT`, plus span export over OTLP when `OTEL_EXPORTER_OTLP_ENDPOINT` is set.
+///
+/// W3C trace context is always propagated, so Thinner stays in the caller's
+/// trace even when it does not export spans itself.
+pub fn init() {
+    global::set_text_map_propagator(TraceContextPropagator::new());
+
+    let service_name = env::var("OTEL_SERVICE_NAME").unwrap_or_else(|_| "thinner".to_string());
+    let config =
+        trace::config().with_resource(Resource::new([KeyValue::new("service.name", service_name)]));
+    let tracer = match env::var("OTEL_EXPORTER_OTLP_ENDPOINT") {
+        Ok(endpoint) => opentelemetry_otlp::new_pipeline()
+            .tracing()
+            .with_exporter(
+                opentelemetry_otlp::new_exporter()
+                    .tonic()
+                    .with_endpoint(endpoint),
+            )
+            .with_trace_config(config)
+            .install_batch(runtime::Tokio)
+            .unwrap_or_else(|e| panic!("Failed to set up the OTLP exporter: {}", e)),
+        // Without a collector spans are still created, just never exported.
+        Err(_) => {
+            let provider = trace::TracerProvider::builder().with_config(config).build();
+            let tracer = provider.tracer("thinner");
+            // The tracer only holds a weak reference, so the provider has to live globally.
+            global::set_tracer_provider(provider);
+            tracer
+        }
+    };
+
+    // JSON by default so access log lines can be shipped as-is; `LOG_FORMAT=text` for humans.
+    let fmt = tracing_subscriber::fmt::layer();
+    let fmt = match env::var("LOG_FORMAT").as_deref() {
+        Ok("text") => fmt.boxed(),
+        Ok("json") | Err(_) => fmt.json().flatten_event(true).with_span_list(false).boxed(),
+        Ok(other) => panic!("$LOG_FORMAT must be json or text, not `{}`.", other),
+    };
+
+    tracing_subscriber::registry()
+        .with(LevelFilter::INFO)
+        .with(fmt)
+        .with(tracing_opentelemetry::layer().with_tracer(tracer))
+        .init();
+}
+
+/// Flushes spans still waiting in the batch exporter; call before exiting.
+pub async fn shutdown() {
+    // Shutting down blocks until the exporter is done, so keep it off the async workers.
+    let _ = tokio::task::spawn_blocking(global::shutdown_tracer_provider).await;
+}
+
+/// Runs every request inside a server span, continuing the trace from an
+/// incoming `traceparent` header if there is one.
+pub async fn trace<B>(request: Request<B>, next: Next<B>) -> Response {
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map_or("unmatched", |p| p.as_str())
+        .to_string();
+    let span = tracing::info_span!(
+        "request",
+        otel.name = %format!("{} {}", request.method(), route),
+        otel.kind = "server",
+        http.method = %request.method(),
+        http.route = %route,
+        http.status_code = field::Empty,
+    );
+    let parent =
+        global::get_text_map_propagator(|p| p.extract(&HeaderExtractor(request.headers())));
+    span.set_parent(parent);
+
+    let response = next.run(request).instrument(span.clone()).await;
+    span.record("http.status_code", response.status().as_u16());
+    response
+}
+
+/// Client span for one StationAPI call attempt.
+pub fn upstream_span(method: &str, origin: &str) -> Span {
+    tracing::info_span!(
+        "upstream",
+        otel.name = %format!("StationAPI/{}", method),
+        otel.kind = "client",
+        rpc.system = "grpc",
+        rpc.method = %method,
+        server.address = %origin,
+    )
+}
+
+/// Writes `span`'s trace context into outgoing gRPC metadata as `traceparent`.
+pub fn inject(span: &Span, metadata: &mut MetadataMap) {
+    let context = span.context();
+    global::get_text_map_propagator(|p| {
+        p.inject_context(&context, &mut MetadataInjector(metadata))
+    });
+}
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|v| v.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|k| k.as_str()).collect()
+    }
+}
+
+struct MetadataInjector<'a>(&'a mut MetadataMap);
+
+impl Injector for MetadataInjector<'_> {
+    fn set(&mut self, key: &str, value: String) {
+        if let (Ok(key), Ok(value)) = (
+            MetadataKey::from_bytes(key.as_bytes()),
+            MetadataValue::try_from(value),
+        ) {
+            self.0.insert(key, value);
+        }
+    }
+}
@@ -0,0 +1,611 @@
+use std::{
+    cell::RefCell,
+    error::Error,
+    future::Future,
+    pin::Pin,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use http::{uri::Scheme, Uri};
+use http_body::Body as _;
+use hyper::client::HttpConnector;
+use hyper_tls::HttpsConnector;
+use rand::Rng;
+use serde::Serialize;
+use tokio::{sync::Mutex, task::JoinSet};
+use tonic::{
+    body::BoxBody,
+    transport::{Channel, ClientTlsConfig, Endpoint},
+    Code, Status,
+};
+use tonic_web::{GrpcWebCall, GrpcWebClientLayer, GrpcWebClientService};
+use tower::Service;
+use tracing::Instrument;
+
+use crate::{
+    access_log::{self, REQUEST_ID_HEADER},
+    breaker::{
+        BoxError, BreakerStatus, CircuitBreaker, CircuitBreakerLayer, CircuitBreakerService,
+    },
+    error::AppError,
+    geo::sort_by_distance,
+    metrics::METRICS,
+    station_api::{self, station_api_client::StationApiClient, Line, Station},
+    telemetry,
+};
+
+/// How long an idle pooled connection to StationAPI is kept around.
+const POOL_IDLE_TIMEOUT: Duration = Duration::from_secs(90);
+
+/// How often HTTP/2 pings keep a native gRPC connection alive.
+const HTTP2_KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Tokyo Station; health checks ask StationAPI for the station nearest to it.
+pub const PROBE_COORDINATES: (f64, f64) = (35.681236, 139.767125);
+
+tokio::task_local! {
+    /// Origin of the upstream that answered the last call in the current request.
+    static SERVED_BY: RefCell<Option<String>>;
+}
+
+type HttpClient = hyper::Client<HttpsConnector<HttpConnector>, GrpcWebCall<BoxBody>>;
+
+/// Whichever transport was configured, with its response body type erased.
+#[derive(Clone)]
+enum Transport {
+    GrpcWeb(GrpcWebClientService<HttpClient>),
+    Channel(Channel),
+}
+
+impl Service<http::Request<BoxBody>> for Transport {
+    type Response = http::Response<BoxBody>;
+    type Error = BoxError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        match self {
+            Transport::GrpcWeb(svc) => svc.poll_ready(cx).map_err(Into::into),
+            Transport::Channel(channel) => channel.poll_ready(cx).map_err(Into::into),
+        }
+    }
+
+    fn call(&mut self, request: http::Request<BoxBody>) -> Self::Future {
+        match self {
+            Transport::GrpcWeb(svc) => {
+                let future = svc.call(request);
+                Box::pin(async move { Ok(future.await?.map(|body| body.boxed_unsync())) })
+            }
+            Transport::Channel(channel) => {
+                let future = channel.call(request);
+                Box::pin(async move {
+                    Ok(future.await?.map(|body| {
+                        body.map_err(|e| Status::from_error(Box::new(e)))
+                            .boxed_unsync()
+                    }))
+                })
+            }
+        }
+    }
+}
+
+type Inner = StationApiClient<CircuitBreakerService<Transport>>;
+
+/// How Thinner talks to StationAPI.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransportKind {
+    /// gRPC-web over HTTP/1.1 or HTTP/2, e.g. through a public gateway.
+    GrpcWeb,
+    /// Native gRPC over HTTP/2, with TLS when `SAPI_URL` is `https://`.
+    Grpc,
+    /// Native gRPC over plaintext HTTP/2 with prior knowledge, for in-cluster use.
+    H2c,
+}
+
+impl FromStr for TransportKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "grpc-web" => Ok(TransportKind::GrpcWeb),
+            "grpc" => Ok(TransportKind::Grpc),
+            "h2c" => Ok(TransportKind::H2c),
+            _ => Err(format!("`{}` is not one of grpc-web, grpc or h2c.", s)),
+        }
+    }
+}
+
+/// Deadline and retry behaviour for every StationAPI call.
+#[derive(Debug, Clone, Copy)]
+pub struct CallPolicy {
+    /// How long a single attempt may take before we give up on it.
+    pub timeout: Duration,
+    /// How many times a call is retried after a retryable failure.
+    pub max_retries: u32,
+    /// Backoff before the first retry; doubled for every further one.
+    pub backoff_base: Duration,
+    /// Upper bound for the backoff before jitter is applied.
+    pub backoff_max: Duration,
+}
+
+impl CallPolicy {
+    /// Exponential backoff with full jitter for the given retry (starting at 0).
+    fn backoff(&self, retry: u32) -> Duration {
+        let ceiling = self
+            .backoff_base
+            .saturating_mul(2u32.saturating_pow(retry))
+            .min(self.backoff_max);
+        ceiling.mul_f64(rand::thread_rng().gen_range(0.0..=1.0))
+    }
+}
+
+/// One StationAPI deployment.
+struct Upstream {
+    origin: String,
+    inner: Inner,
+    breaker: Arc<CircuitBreaker>,
+    /// Result of the last health check.
+    healthy: AtomicBool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UpstreamStatus {
+    pub origin: String,
+    pub healthy: bool,
+    pub circuit_breaker: BreakerStatus,
+}
+
+impl Upstream {
+    fn is_available(&self) -> bool {
+        self.healthy.load(Ordering::Relaxed) && self.breaker.is_available()
+    }
+
+    /// Cheap liveness probe: a single nearest-station lookup with no retries.
+    async fn probe(&self, timeout: Duration) -> bool {
+        let mut inner = self.inner.clone();
+        let (latitude, longitude) = PROBE_COORDINATES;
+        let mut request = tonic::Request::new(station_api::GetStationByCoordinatesRequest {
+            latitude,
+            longitude,
+            limit: Some(1),
+        });
+        request.set_timeout(timeout);
+
+        let attempt = self.breaker.attempt(&mut request);
+        match tokio::time::timeout(timeout, inner.get_stations_by_coordinates(request)).await {
+            Ok(Ok(_)) => {
+                attempt.succeeded();
+                true
+            }
+            Ok(Err(status)) => {
+                attempt.failed(&status);
+                false
+            }
+            Err(_) => {
+                attempt.timed_out();
+                false
+            }
+        }
+    }
+}
+
+/// How `/readyz` probes StationAPI.
+#[derive(Debug, Clone, Copy)]
+pub struct ReadyProbe {
+    /// How long a probe result is reused.
+    pub max_age: Duration,
+    /// Deadline for a probe, kept well under orchestrator probe timeouts.
+    pub timeout: Duration,
+}
+
+/// StationAPI client balancing over one or more upstreams; clones share the
+/// underlying connection pools.
+#[derive(Clone)]
+pub struct Client {
+    upstreams: Arc<[Upstream]>,
+    next: Arc<AtomicUsize>,
+    policy: CallPolicy,
+    /// When readiness was last probed, and the result.
+    readiness: Arc<Mutex<Option<(Instant, bool)>>>,
+}
+
+/// Builds the StationAPI client once at startup from a comma-separated list of origins.
+pub fn build_client(
+    sapi_urls: &str,
+    transport: TransportKind,
+    policy: CallPolicy,
+    new_breaker: impl Fn() -> CircuitBreaker,
+) -> Result<Client, Box<dyn Error>> {
+    let upstreams = sapi_urls
+        .split(',')
+        .map(str::trim)
+        .filter(|url| !url.is_empty())
+        .map(|url| build_upstream(url, transport, new_breaker()))
+        .collect::<Result<Arc<[_]>, _>>()?;
+    if upstreams.is_empty() {
+        return Err("SAPI_URL must name at least one upstream.".into());
+    }
+
+    Ok(Client {
+        upstreams,
+        next: Arc::new(AtomicUsize::new(0)),
+        policy,
+        readiness: Arc::new(Mutex::new(None)),
+    })
+}
+
+fn build_upstream(
+    sapi_url: &str,
+    transport: TransportKind,
+    breaker: CircuitBreaker,
+) -> Result<Upstream, Box<dyn Error>> {
+    let origin: Uri = sapi_url.parse()?;
+
+    let transport = match transport {
+        TransportKind::GrpcWeb => grpc_web_transport(),
+        TransportKind::Grpc | TransportKind::H2c => channel_transport(origin.clone(), transport)?,
+    };
+
+    let breaker = Arc::new(breaker);
+    let svc = tower::ServiceBuilder::new()
+        .layer(CircuitBreakerLayer::new(Arc::clone(&breaker)))
+        .service(transport);
+
+    Ok(Upstream {
+        origin: sapi_url.to_string(),
+        inner: StationApiClient::with_origin(svc, origin),
+        breaker,
+        healthy: AtomicBool::new(true),
+    })
+}
+
+fn grpc_web_transport() -> Transport {
+    let mut http = HttpConnector::new();
+    http.set_keepalive(Some(POOL_IDLE_TIMEOUT));
+    http.enforce_http(false);
+    let https = HttpsConnector::new_with_connector(http);
+    let client: HttpClient = hyper::Client::builder()
+        .pool_idle_timeout(POOL_IDLE_TIMEOUT)
+        .build(https);
+
+    Transport::GrpcWeb(
+        tower::ServiceBuilder::new()
+            .layer(GrpcWebClientLayer::new())
+            .service(client),
+    )
+}
+
+/// A lazily connecting tonic channel; it multiplexes every call over HTTP/2.
+fn channel_transport(origin: Uri, transport: TransportKind) -> Result<Transport, Box<dyn Error>> {
+    let https = origin.scheme() == Some(&Scheme::HTTPS);
+    if transport == TransportKind::H2c && https {
+        return Err("h2c needs a plaintext `http://` SAPI_URL.".into());
+    }
+
+    let mut endpoint = Endpoint::from(origin)
+        .tcp_keepalive(Some(POOL_IDLE_TIMEOUT))
+        .http2_keep_alive_interval(HTTP2_KEEP_ALIVE_INTERVAL)
+        .keep_alive_while_idle(true);
+    if https {
+        endpoint = endpoint.tls_config(ClientTlsConfig::new())?;
+    }
+
+    Ok(Transport::Channel(endpoint.connect_lazy()))
+}
+
+/// Whether a failed attempt is worth repeating.
+fn is_retryable(error: &AppError) -> bool {
+    match error {
+        AppError::UpstreamTimeout => true,
+        AppError::Upstream(status) => status.code() == Code::Unavailable,
+        _ => false,
+    }
+}
+
+/// Runs `future` while remembering which upstream answered it, see [`Client::call`].
+pub async fn track_served_by<F: Future>(future: F) -> (Option<String>, F::Output) {
+    SERVED_BY
+        .scope(RefCell::new(None), async {
+            let output = future.await;
+            let served_by = SERVED_BY.with(|s| s.borrow_mut().take());
+            (served_by, output)
+        })
+        .await
+}
+
+impl Client {
+    pub fn status(&self) -> Vec<UpstreamStatus> {
+        self.upstreams
+            .iter()
+            .map(|u| UpstreamStatus {
+                origin: u.origin.clone(),
+                healthy: u.healthy.load(Ordering::Relaxed),
+                circuit_breaker: u.breaker.status(),
+            })
+            .collect()
+    }
+
+    /// Probes every upstream in the background and takes failing ones out of rotation.
+    pub fn spawn_health_checks(&self, interval: Duration) {
+        let client = self.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                for upstream in client.upstreams.iter() {
+                    let healthy = upstream.probe(client.policy.timeout).await;
+                    if upstream.healthy.swap(healthy, Ordering::Relaxed) != healthy {
+                        tracing::warn!(
+                            "StationAPI upstream {} is now {}.",
+                            upstream.origin,
+                            if healthy { "healthy" } else { "unhealthy" }
+                        );
+                    }
+                }
+            }
+        });
+    }
+
+    /// Whether any upstream answers a probe, probing at most once per `probe.max_age`.
+    ///
+    /// All upstreams are probed at once, so this takes at most `probe.timeout`;
+    /// concurrent callers wait for the probe in flight instead of starting their own.
+    pub async fn is_ready(&self, probe: ReadyProbe) -> bool {
+        let mut last = self.readiness.lock().await;
+        if let Some((probed_at, ready)) = *last {
+            if probed_at.elapsed() < probe.max_age {
+                return ready;
+            }
+        }
+
+        let mut probes = JoinSet::new();
+        for index in 0..self.upstreams.len() {
+            let client = self.clone();
+            probes.spawn(async move { client.upstreams[index].probe(probe.timeout).await });
+        }
+        let mut ready = false;
+        while let Some(result) = probes.join_next().await {
+            if result.unwrap_or(false) {
+                ready = true;
+                break;
+            }
+        }
+        *last = Some((Instant::now(), ready));
+        ready
+    }
+
+    /// Round-robin choice among the upstreams not yet `tried`, preferring available ones.
+    fn pick(&self, tried: &[usize]) -> Option<usize> {
+        let len = self.upstreams.len();
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        let untried = (0..len)
+            .map(|i| (start + i) % len)
+            .filter(|i| !tried.contains(i));
+
+        untried
+            .clone()
+            .find(|&i| self.upstreams[i].is_available())
+            .or_else(|| untried.clone().next())
+    }
+
+    /// Sends `message` through `rpc` under the call policy and unwraps the reply.
+    ///
+    /// A failing upstream is failed over to the next one right away. Once every
+    /// upstream has been tried, timeouts and unavailability are retried with
+    /// jittered exponential backoff.
+    async fn call<M, T, F, Fut>(&self, method: &str, message: M, rpc: F) -> Result<T, AppError>
+    where
+        M: Clone,
+        F: Fn(Inner, tonic::Request<M>) -> Fut,
+        Fut: Future<Output = Result<tonic::Response<T>, tonic::Status>>,
+    {
+        let mut tried = Vec::with_capacity(self.upstreams.len());
+        let mut retry = 0;
+        loop {
+            let index = self
+                .pick(&tried)
+                .expect("A round always has an untried upstream.");
+            tried.push(index);
+            let upstream = &self.upstreams[index];
+
+            let span = telemetry::upstream_span(method, &upstream.origin);
+            let mut request = tonic::Request::new(message.clone());
+            request.set_timeout(self.policy.timeout);
+            telemetry::inject(&span, request.metadata_mut());
+            if let Some(id) = access_log::request_id().and_then(|id| id.to_str().ok()?.parse().ok())
+            {
+                request.metadata_mut().insert(REQUEST_ID_HEADER, id);
+            }
+
+            let attempt = upstream.breaker.attempt(&mut request);
+            let start = Instant::now();
+            let result =
+                tokio::time::timeout(self.policy.timeout, rpc(upstream.inner.clone(), request))
+                    .instrument(span)
+                    .await;
+            access_log::record_upstream_latency(start.elapsed());
+            match &result {
+                Ok(Ok(_)) => attempt.succeeded(),
+                Ok(Err(status)) => attempt.failed(status),
+                Err(_) => attempt.timed_out(),
+            }
+            let (code, error) = match result {
+                Ok(Ok(response)) => {
+                    METRICS.observe_upstream(method, &upstream.origin, "Ok", start.elapsed());
+                    let _ = SERVED_BY.try_with(|s| *s.borrow_mut() = Some(upstream.origin.clone()));
+                    return Ok(response.into_inner());
+                }
+                Ok(Err(status)) => (format!("{:?}", status.code()), AppError::from(status)),
+                Err(_) => (
+                    format!("{:?}", Code::DeadlineExceeded),
+                    AppError::UpstreamTimeout,
+                ),
+            };
+            // A call the breaker rejected never reached StationAPI, so give it its own code.
+            let code = match error {
+                AppError::CircuitOpen(_) => "CircuitOpen",
+                _ => code.as_str(),
+            };
+            METRICS.observe_upstream(method, &upstream.origin, code, start.elapsed());
+
+            let can_fail_over = tried.len() < self.upstreams.len()
+                && (is_retryable(&error) || matches!(error, AppError::CircuitOpen(_)));
+            if can_fail_over {
+                tracing::warn!("Failing over from {}: {}", upstream.origin, error);
+                continue;
+            }
+
+            if retry >= self.policy.max_retries || !is_retryable(&error) {
+                return Err(error);
+            }
+
+            let backoff = self.policy.backoff(retry);
+            tracing::warn!("Retrying a StationAPI call in {:?}: {}", backoff, error);
+            tokio::time::sleep(backoff).await;
+            retry += 1;
+            tried.clear();
+        }
+    }
+}
+
+/// StationAPI answers unknown ids with `NotFound`; this says what was missing.
+fn not_found(missing: AppError) -> impl FnOnce(AppError) -> AppError {
+    move |e| match e {
+        AppError::Upstream(status) if status.code() == Code::NotFound => missing,
+        e => e,
+    }
+}
+
+/// Returns up to `limit` stations around the given point, nearest first.
+pub async fn fetch_nearby(
+    client: &Client,
+    latitude: f64,
+    longitude: f64,
+    limit: u32,
+) -> Result<Vec<Station>, AppError> {
+    let request = station_api::GetStationByCoordinatesRequest {
+        latitude,
+        longitude,
+        limit: Some(limit),
+    };
+
+    let mut stations = client
+        .call(
+            "get_stations_by_coordinates",
+            request,
+            |mut c, r| async move { c.get_stations_by_coordinates(r).await },
+        )
+        .await
+        .map_err(not_found(AppError::StationNotFound))?
+        .stations;
+    if stations.is_empty() {
+        return Err(AppError::StationNotFound);
+    }
+
+    sort_by_distance(&mut stations, latitude, longitude);
+    Ok(stations)
+}
+
+pub async fn fetch_station(client: &Client, id: u32) -> Result<Station, AppError> {
+    let request = station_api::GetStationByIdRequest { id };
+
+    client
+        .call("get_station_by_id", request, |mut c, r| async move {
+            c.get_station_by_id(r).await
+        })
+        .await
+        .map_err(not_found(AppError::StationNotFound))?
+        .station
+        .ok_or(AppError::StationNotFound)
+}
+
+pub async fn fetch_stations(client: &Client, ids: Vec<u32>) -> Result<Vec<Station>, AppError> {
+    let request = station_api::GetStationByIdListRequest { ids };
+
+    let stations = client
+        .call("get_station_by_id_list", request, |mut c, r| async move {
+            c.get_station_by_id_list(r).await
+        })
+        .await
+        .map_err(not_found(AppError::StationNotFound))?
+        .stations;
+    if stations.is_empty() {
+        return Err(AppError::StationNotFound);
+    }
+    Ok(stations)
+}
+
+/// Searches stations by name; an empty result is not an error here.
+pub async fn search_stations(
+    client: &Client,
+    station_name: String,
+    limit: u32,
+) -> Result<Vec<Station>, AppError> {
+    let request = station_api::GetStationsByNameRequest {
+        station_name,
+        limit: Some(limit),
+    };
+
+    Ok(client
+        .call("get_stations_by_name", request, |mut c, r| async move {
+            c.get_stations_by_name(r).await
+        })
+        .await
+        .map_err(not_found(AppError::StationNotFound))?
+        .stations)
+}
+
+/// Returns the stops of a line in running order.
+pub async fn fetch_line_stations(client: &Client, line_id: u32) -> Result<Vec<Station>, AppError> {
+    let request = station_api::GetStationByLineIdRequest { line_id };
+
+    let stations = client
+        .call("get_stations_by_line_id", request, |mut c, r| async move {
+            c.get_stations_by_line_id(r).await
+        })
+        .await
+        .map_err(not_found(AppError::LineNotFound))?
+        .stations;
+    if stations.is_empty() {
+        return Err(AppError::LineNotFound);
+    }
+    Ok(stations)
+}
+
+pub async fn fetch_line(client: &Client, line_id: u32) -> Result<Line, AppError> {
+    let request = station_api::GetLineByIdRequest { line_id };
+
+    client
+        .call("get_line_by_id", request, |mut c, r| async move {
+            c.get_line_by_id(r).await
+        })
+        .await
+        .map_err(not_found(AppError::LineNotFound))?
+        .line
+        .ok_or(AppError::LineNotFound)
+}
+
+/// Searches lines by name; an empty result is not an error here.
+pub async fn search_lines(
+    client: &Client,
+    line_name: String,
+    limit: u32,
+) -> Result<Vec<Line>, AppError> {
+    let request = station_api::GetLinesByNameRequest {
+        line_name,
+        limit: Some(limit),
+    };
+
+    Ok(client
+        .call("get_lines_by_name", request, |mut c, r| async move {
+            c.get_lines_by_name(r).await
+        })
+        .await
+        .map_err(not_found(AppError::LineNotFound))?
+        .lines)
+}